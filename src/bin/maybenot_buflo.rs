@@ -0,0 +1,67 @@
+// Maybenot BuFLO -- uses constant-rate traffic for a minimum duration to approximate the BuFLO defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+
+use maybenot_defenses::machines::{constant_dist, transition_to, generate_start_state, generate_block_state, generate_cell_state, generate_send_state, generate_unblock_state};
+
+use maybenot::{
+machine::Machine,
+state::State
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() == 3, "Usage: {} <interval> <min duration>", &args[0]);
+    
+    let interval:     f64 = args[1].parse().expect("Invalid interval");     // BuFLO param = rho, time between cells (ms)
+    let min_duration: f64 = args[2].parse().expect("Invalid min duration"); // BuFLO param = tau, minimum time to send for (sec)
+    
+    // The packet size (BuFLO param = d) is fixed by Tor's cells
+    let machine = generate_machine(interval * 1000.0, min_duration * 1000000.0);
+    println!("Machine: {} ({})\n", machine, machine.len());
+}
+
+// Generate a BuFLO machine (the same on both sides). Once there is traffic, all cells are
+// held back and sent every interval, in place of padding. After the minimum duration, the
+// machine stops blocking and ends as soon as a cell goes out with no non-padding cell
+// waiting to take its place.
+fn generate_machine(interval: f64, min_duration: f64) -> String {
+    // Set up state vector
+    let num_states = 6;
+    let min_cells = (min_duration / interval).ceil();
+    
+    let timeout = constant_dist(interval);
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and BLOCK states
+    states.push(generate_start_state(1, num_states));
+    if min_cells >= 1.0 {
+        states.push(generate_block_state(transition_to(2), 0.0, num_states));
+    } else {
+        states.push(generate_block_state(transition_to(3), 0.0, num_states));
+    }
+    
+    // MIN state, which sends min_cells cells, one every interval
+    states.push(generate_send_state(min_cells, &timeout, 2, transition_to(3), num_states));
+    
+    // WAIT and DATA states: PaddingSent in WAIT means the cell was padding, so the page is done
+    states.push(generate_cell_state(Some(4), 5, &timeout, num_states));
+    states.push(generate_cell_state(None, 3, &timeout, num_states));
+    
+    // UNBLOCK state
+    states.push(generate_unblock_state(num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
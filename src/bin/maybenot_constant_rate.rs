@@ -0,0 +1,92 @@
+// Maybenot constant-rate -- sends cover traffic at a fixed or jittered rate while a connection is active
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::collections::HashMap;
+
+use maybenot_defenses::trace::TOR_CELL_SIZE;
+use maybenot_defenses::machines::{constant_dist, uniform_dist, transition_to, generate_start_state, generate_pad_state};
+
+use maybenot::{
+machine::Machine,
+event::Event,
+state::State,
+dist::Dist
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() >= 3 && args.len() <= 5, "Usage: {} <rate> <idle timeout> [<jitter> [<bandwidth cap>]]", &args[0]);
+    
+    let rate:         f64 = args[1].parse().expect("Invalid rate");         // cells / sec
+    let idle_timeout: f64 = args[2].parse().expect("Invalid idle timeout"); // time without non-padding cells after which padding stops (sec)
+    assert!(rate > 0.0, "Rate must be positive");
+    
+    let mut jitter = 0.0; // variation of the time between cells, as a fraction of it
+    if args.len() >= 4 {
+        jitter = args[3].parse::<f64>().expect("Invalid jitter") / 100.0;
+        assert!(jitter >= 0.0 && jitter < 1.0, "Jitter must be at least 0% and below 100%");
+    }
+    
+    let mut min_interval = 0.0; // shortest time between cells, from the bandwidth cap (microseconds)
+    if args.len() >= 5 {
+        let cap: f64 = args[4].parse().expect("Invalid bandwidth cap"); // Mbps, one bit per microsecond
+        assert!(cap > 0.0, "Bandwidth cap must be positive");
+        min_interval = TOR_CELL_SIZE * 8.0 / cap;
+    }
+    
+    let machine = generate_machine(1000000.0 / rate, jitter, min_interval, idle_timeout * 1000000.0);
+    println!("Machine: {} ({})\n", machine, machine.len());
+}
+
+// Generate a constant-rate machine (the same on both sides). After the first non-padding
+// cell either way, padding is sent every interval, varied by up to the jitter but never
+// closer than min_interval. The two SEND states are the same, and each non-padding cell
+// moves from one to the other, which restarts the count of padding cells towards the idle
+// timeout (and the time until the next padding cell). Padding stops once a whole idle
+// timeout passes with only padding, until the next non-padding cell.
+fn generate_machine(interval: f64, jitter: f64, min_interval: f64, idle_timeout: f64) -> String {
+    // Set up state vector
+    let num_states = 3;
+    let idle_cells = (idle_timeout / interval).ceil().max(1.0);
+    
+    let timeout = uniform_dist(f64::max(interval * (1.0 - jitter), min_interval), f64::max(interval * (1.0 + jitter), min_interval));
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and SEND states
+    states.push(generate_start_state(1, num_states));
+    states.push(generate_send_state(1, 2, &timeout, idle_cells, num_states));
+    states.push(generate_send_state(2, 1, &timeout, idle_cells, num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: 0,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
+
+fn generate_send_state(curr_index: usize, other_index: usize, timeout: &Dist, idle_cells: f64, num_states: usize) -> State {
+    // PaddingSent --> SEND (100%)
+    // NonPaddingSent, NonPaddingRecv --> other SEND (100%)
+    // LimitReached --> START (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::PaddingSent, transition_to(curr_index));
+    transitions.insert(Event::NonPaddingSent, transition_to(other_index));
+    transitions.insert(Event::NonPaddingRecv, transition_to(other_index));
+    transitions.insert(Event::LimitReached, transition_to(0));
+    
+    // SEND state (padding only, so no bypass)
+    let mut state = generate_pad_state(transitions, timeout, num_states);
+    state.bypass = false;
+    
+    state.limit = constant_dist(idle_cells);
+    
+    return state;
+}
@@ -0,0 +1,95 @@
+// Maybenot CS-BuFLO -- uses adaptive-rate traffic, padded to a power of two cells, to approximate the CS-BuFLO defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+
+use maybenot_defenses::machines::{constant_dist, transition_to, generate_start_state, generate_block_state, generate_cell_state, generate_send_state, generate_unblock_state};
+
+use maybenot::{
+machine::Machine,
+state::State
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() == 4, "Usage: {} <min interval> <rate levels> <max cells>", &args[0]);
+    
+    let min_interval: f64   = args[1].parse().expect("Invalid min interval"); // CS-BuFLO param = rho at the fastest rate, time between cells (ms)
+    let num_levels:   usize = args[2].parse().expect("Invalid rate levels");  // rates to adapt between, each half the one before
+    let max_cells:    f64   = args[3].parse().expect("Invalid max cells");    // cells after which the machine ends regardless
+    assert!(num_levels >= 1, "There must be at least one rate level");
+    assert!(max_cells >= 1.0, "Max cells must be at least 1");
+    
+    let machine = generate_machine(min_interval * 1000.0, num_levels, max_cells);
+    println!("Machine: {} ({})\n", machine, machine.len());
+}
+
+// Generate a CS-BuFLO machine (the same on both sides). Once there is traffic, all cells are
+// held back and sent at one of num_levels rates, in place of padding. Cells are sent in
+// epochs that end when 1, 2, 4, 8, ... cells have been sent in total, and the first cell of
+// each epoch adapts the rate: if a non-padding cell took its place, the rate doubles for the
+// epoch, and otherwise it halves. Padding in that cell at the slowest rate means the page is
+// done, so the machine sends the rest of the epoch as padding, stops blocking at a power of
+// two cells, and ends.
+fn generate_machine(min_interval: f64, num_levels: usize, max_cells: f64) -> String {
+    // Set up state vector
+    let last_epoch = max_cells.log2().ceil() as usize;
+    let epoch_index = 2;
+    let term_index = epoch_index + 3 * (last_epoch + 1) * num_levels;
+    let unblock_index = term_index + last_epoch + 1;
+    let num_states = unblock_index + 1;
+    
+    let timeouts: Vec<_> = (0..num_levels).map(|level| constant_dist(min_interval * 2f64.powi(level as i32))).collect();
+    let wait_index = |epoch: usize, level: usize| epoch_index + 3 * (epoch * num_levels + level);
+    let epoch_cells = |epoch: usize| if epoch == 0 { 1.0 } else { 2f64.powi(epoch as i32 - 1) };
+    
+    // The state after the epoch at the given level, and the state after its first cell
+    let next_index = |epoch: usize, level: usize| if epoch == last_epoch { unblock_index } else { wait_index(epoch + 1, level) };
+    let rest_index = |epoch: usize, level: usize| if epoch_cells(epoch) > 1.0 { wait_index(epoch, level) + 2 } else { next_index(epoch, level) };
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and BLOCK states (the fastest rate first, as pages start with a burst)
+    states.push(generate_start_state(1, num_states));
+    states.push(generate_block_state(transition_to(wait_index(0, 0)), 0.0, num_states));
+    
+    // WAIT, DATA and REST states of each epoch and rate level. The first cell of an epoch goes
+    // to DATA if it was non-padding, and on to the faster rate from there, while PaddingSent in
+    // WAIT moves to the slower rate (or TERM, at the slowest rate).
+    for epoch in 0..=last_epoch {
+        for level in 0..num_levels {
+            let padding_index = if level + 1 < num_levels {
+                rest_index(epoch, level + 1)
+            } else if epoch_cells(epoch) > 1.0 {
+                term_index + epoch
+            } else {
+                unblock_index
+            };
+            let data_index = rest_index(epoch, level.saturating_sub(1));
+            
+            states.push(generate_cell_state(Some(wait_index(epoch, level) + 1), padding_index, &timeouts[level], num_states));
+            states.push(generate_cell_state(None, data_index, &timeouts[level], num_states));
+            states.push(generate_send_state(epoch_cells(epoch) - 1.0, &timeouts[level], wait_index(epoch, level) + 2, transition_to(next_index(epoch, level)), num_states));
+        }
+    }
+    
+    // TERM states, which finish the epoch as padding at the slowest rate
+    for epoch in 0..=last_epoch {
+        states.push(generate_send_state(epoch_cells(epoch) - 1.0, &timeouts[num_levels - 1], term_index + epoch, transition_to(unblock_index), num_states));
+    }
+    
+    // UNBLOCK state
+    states.push(generate_unblock_state(num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
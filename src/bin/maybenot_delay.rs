@@ -0,0 +1,113 @@
+// Maybenot delay -- blocks outgoing cells for short random periods to obfuscate their timing
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::collections::HashMap;
+
+use maybenot_defenses::machines::{constant_dist, uniform_dist, parse_dist, transition_to, generate_idle_state};
+
+use maybenot::{
+machine::Machine,
+event::Event,
+state::State,
+dist::Dist
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() >= 2, "Usage: {} <delay> [<probability>] [--cap <ms>]", &args[0]);
+    
+    let mut delay: Option<Dist> = None; // blocking duration (microseconds)
+    let mut probability = 1.0;          // probability of blocking after each non-padding cell sent
+    let mut cap = 0.0;                  // maximum blocking duration (microseconds, 0 for no cap)
+    
+    let mut i = 1;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            match delay {
+                None => delay = Some(parse_delay(&args[i])),
+                Some(_) => probability = args[i].parse().expect("Invalid probability"),
+            }
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--cap" => cap = args[i + 1].parse::<f64>().expect("Invalid cap") * 1000.0,
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
+    
+    let mut delay = delay.expect("Missing delay");
+    assert!(probability > 0.0 && probability <= 1.0, "Probability must be above 0 and at most 1");
+    assert!(cap >= 0.0, "Cap must not be negative");
+    
+    // The cap only ever lowers a max given in the distribution
+    if cap > 0.0 && (delay.max == 0.0 || cap < delay.max) {
+        delay.max = cap;
+    }
+    
+    let machine = generate_machine(delay, probability);
+    println!("Machine: {} ({})\n", machine, machine.len());
+}
+
+// Parse a delay: a number is the maximum delay in milliseconds, drawn uniformly from 0, and
+// anything else is a distribution, <dist>:<param1>:<param2>[:<start>:<max>] in microseconds.
+fn parse_delay(spec: &str) -> Dist {
+    if let Ok(max_delay) = spec.parse::<f64>() {
+        assert!(max_delay > 0.0, "Delay must be positive");
+        return uniform_dist(0.0, max_delay * 1000.0);
+    }
+    
+    return parse_dist(spec);
+}
+
+// Generate a delay machine (the same on both sides). After a non-padding cell is sent,
+// WAIT moves to BLOCK with the given probability, which blocks outgoing cells for a
+// duration from the delay distribution and moves back to WAIT. Cells sent when the
+// blocking ends can start the next blocking period.
+fn generate_machine(delay: Dist, probability: f64) -> String {
+    // Set up state vector
+    let num_states = 2;
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // WAIT state, which moves to BLOCK with the given probability after each non-padding cell sent
+    let mut nonpadding_sent: HashMap<usize, f64> = HashMap::new();
+    nonpadding_sent.insert(1, probability);
+    states.push(generate_idle_state(nonpadding_sent, num_states));
+    
+    // BLOCK state
+    states.push(generate_block_state(delay, num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: 0,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
+
+// Generate the BLOCK state, which blocks outgoing cells for a random duration.
+fn generate_block_state(delay: Dist, num_states: usize) -> State {
+    // BlockingBegin --> WAIT (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::BlockingBegin, transition_to(0));
+    
+    // BLOCK state (padding from other machines is held back as well)
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    
+    state.timeout = constant_dist(0.0);
+    
+    state.action = delay;
+    
+    return state;
+}
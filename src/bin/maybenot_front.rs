@@ -7,6 +7,9 @@ use std::f64::consts::E;
 use std::f64::consts::PI;
 use std::collections::HashMap;
 
+use maybenot_defenses::trace::TOR_CELL_SIZE;
+use maybenot_defenses::machines::{constant_dist, uniform_dist, generate_start_state, generate_wait_state};
+
 use maybenot::{
 machine::Machine,
 event::Event,
@@ -14,28 +17,69 @@ state::State,
 dist::{Dist, DistType}
 };
 
-const TOR_CELL_SIZE: f64 = 512.0;
-
 fn main() {
     let args: Vec<String> = env::args().collect();
-    assert!(args.len() == 4, "Usage: {} <padding window> <padding budget> <num states>", &args[0]);
+    assert!(args.len() == 4 || args.len() == 6, "Usage: {} <padding window> <padding budget> <num states> [<quiet period> <max rearms>]", &args[0]);
     
     let padding_window: f64 = args[1].parse().expect("Invalid padding window"); // FRONT param = W_max (sec)
     let padding_budget: u32 = args[2].parse().expect("Invalid padding budget"); // FRONT param = N (num cells)
     let num_states:     u32 = args[3].parse().expect("Invalid num states");     // number of PADDING states
     
-    let machine = generate_machine(padding_window * 1000000.0, padding_budget, num_states as usize);
+    // Optional re-arming for multi-page sessions (disabled by default)
+    let mut quiet_period: f64 = 0.0; // idle time after the schedule completes before re-arming (sec)
+    let mut max_rearms:   u32 = 0;   // number of times the schedule may restart per connection
+    if args.len() == 6 {
+        quiet_period = args[4].parse().expect("Invalid quiet period");
+        max_rearms   = args[5].parse().expect("Invalid max rearms");
+    }
+    
+    let machine = generate_machine(padding_window * 1000000.0, padding_budget, num_states as usize, quiet_period * 1000000.0, max_rearms as usize);
     println!("Machine: {} ({})\n", machine, machine.len());
 }
 
 // Generate a FRONT machine with the specified number of PADDING states.
-fn generate_machine(padding_window: f64, padding_budget: u32, num_states: usize) -> String {
-    let area = 1.0 / (num_states as f64);       // Area under Rayleigh CDF curve of each state
-    let max_t = rayleigh_max_t(padding_window);
+// If max_rearms > 0, the padding schedule is followed by WAIT and ARMED
+// states, and is repeated with a fresh budget up to max_rearms times.
+fn generate_machine(padding_window: f64, padding_budget: u32, num_states: usize, quiet_period: f64, max_rearms: usize) -> String {
+    // Layout: START, then for each cycle: PADDING_0..PADDING_n, WAIT, ARMED
+    // (the last cycle has no WAIT or ARMED state and ends in StateEnd)
+    let total_states = 1 + (max_rearms + 1) * num_states + 2 * max_rearms;
     
     // States
-    let mut states: Vec<State> = Vec::with_capacity(num_states + 1);
-    states.push(generate_start_state(num_states + 1));
+    let mut states: Vec<State> = Vec::with_capacity(total_states);
+    states.push(generate_start_state(1, total_states));
+    
+    for cycle in 0..(max_rearms + 1) {
+        let first_index = states.len();
+        let wait_index = first_index + num_states;
+        
+        if cycle == max_rearms {
+            generate_padding_states(&mut states, first_index, total_states + 1, total_states, padding_window, padding_budget, num_states);
+        } else {
+            generate_padding_states(&mut states, first_index, wait_index, total_states, padding_window, padding_budget, num_states);
+            states.push(generate_wait_state(wait_index, wait_index + 1, quiet_period, total_states));
+            states.push(generate_start_state(wait_index + 2, total_states));
+        }
+    }
+    
+    // Machine
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: if max_rearms > 0 { u64::MAX } else { 0 }, // WAIT blocks for no time
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
+
+// Append one full padding schedule (num_states PADDING states) to the state vector,
+// starting at first_index. The last PADDING state transitions to end_index.
+fn generate_padding_states(states: &mut Vec<State>, first_index: usize, end_index: usize, total_states: usize, padding_window: f64, padding_budget: u32, num_states: usize) {
+    let area = 1.0 / (num_states as f64);       // Area under Rayleigh CDF curve of each state
+    let max_t = rayleigh_max_t(padding_window);
     
     let mut t1 = 0.0;                           // Starting time of next PADDING state
     let mut total_padding_frac = 0.0;           // Area coverage of current PADDING states
@@ -49,7 +93,8 @@ fn generate_machine(padding_window: f64, padding_budget: u32, num_states: usize)
         let timeout = width / padding_count;
         let stdev = (padding_window).powi(2) / (padding_count * middle * PI.sqrt());
         
-        states.push(generate_padding_state(i, i + 1, num_states + 1, padding_count, timeout, stdev));
+        let curr_index = first_index + i - 1;
+        states.push(generate_padding_state(curr_index, curr_index + 1, total_states, padding_count, timeout, stdev));
         
         t1 = t2;
         total_padding_frac += area;
@@ -63,19 +108,7 @@ fn generate_machine(padding_window: f64, padding_budget: u32, num_states: usize)
     let timeout = width / padding_count;
     let stdev = (padding_window).powi(2) / (padding_count * middle * PI.sqrt());
     
-    states.push(generate_padding_state(num_states, num_states + 2, num_states + 1, padding_count, timeout, stdev));
-    
-    // Machine
-    let machine = Machine {
-        allowed_padding_bytes: u64::MAX,
-        max_padding_frac: 0.0,
-        allowed_blocked_microsec: 0,
-        max_blocking_frac: 0.0,
-        states: states,
-        include_small_packets: false,
-    };
-    
-    return machine.serialize();
+    states.push(generate_padding_state(first_index + num_states - 1, end_index, total_states, padding_count, timeout, stdev));
 }
 
 // Generate a PADDING state for a machine.
@@ -102,42 +135,13 @@ fn generate_padding_state(curr_index: usize, next_index: usize, num_states: usiz
         max: (timeout * 2.0),
     };
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: TOR_CELL_SIZE,
-        param2: TOR_CELL_SIZE,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(TOR_CELL_SIZE);
     
-    state.limit = Dist {
-        dist: DistType::Uniform,
-        param1: 1.0,
-        param2: padding_count,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.limit = uniform_dist(1.0, padding_count);
     
     return state;
 }
 
-// Generate the START state for a machine.
-fn generate_start_state(num_states: usize) -> State {
-    // NonPaddingSent --> first PADDING state (100%)
-    let mut nonpadding_sent: HashMap<usize, f64> = HashMap::new();
-    nonpadding_sent.insert(1, 1.0);
-    
-    // NonPaddingRecv --> first PADDING state (100%)
-    let mut nonpadding_recv: HashMap<usize, f64> = HashMap::new();
-    nonpadding_recv.insert(1, 1.0);
-    
-    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
-    transitions.insert(Event::NonPaddingSent, nonpadding_sent);
-    transitions.insert(Event::NonPaddingRecv, nonpadding_recv);
-    
-    return State::new(transitions, num_states);
-}
-
 // Find the width of an interval in the Rayleigh distribution,
 // starting at a, with the specified area. Uses a search algorithm
 // because numerical error affects direct calculation significantly.
@@ -0,0 +1,157 @@
+// Maybenot Interspace -- uses WTF-PAD-style machines with randomized distributions to approximate the Interspace defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::fs;
+use std::collections::HashSet;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use maybenot_defenses::machines::{parse_dist, is_valid_dist};
+use maybenot_defenses::wtfpad;
+
+use maybenot::dist::{Dist, DistType};
+
+const BURST_TIMEOUT: &str  = "lognormal:9.0:1.5"; // default, microseconds without a non-padding cell before a fake burst
+const GAP_TIMEOUT: &str    = "lognormal:7.0:1.0"; // default, microseconds between cells in a fake burst
+const BURST_LENGTH: &str   = "lognormal:1.5:1.0"; // default, cells in a fake burst
+const CONTINUE_PROB: f64   = 0.9;                 // default, probability of waiting for another gap after a fake burst
+const SPREAD: f64          = 2.0;                 // default, factor that parameters are randomized within
+
+// The base configuration that machines are sampled around
+struct Base {
+    burst: Dist,
+    gap: Dist,
+    length: Dist,
+    continue_prob: f64,
+    spread: f64,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = format!("Usage: {} [<num machines>] [--burst <dist>] [--gap <dist>] [--length <dist>] [--continue <prob>] [--spread <factor>] [--seed <seed>] [--output <dir>]", &args[0]);
+    
+    let mut base = Base {
+        burst: parse_dist(BURST_TIMEOUT),
+        gap: parse_dist(GAP_TIMEOUT),
+        length: parse_dist(BURST_LENGTH),
+        continue_prob: CONTINUE_PROB,
+        spread: SPREAD,
+    };
+    
+    let mut num_machines: usize = 1;
+    let mut rng = StdRng::from_entropy(); // randomness for sampling distributions
+    let mut output_dir: Option<String> = None;
+    
+    let mut i = 1;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            num_machines = args[i].parse().expect("Invalid number of machines");
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--burst" => base.burst = parse_dist(&args[i + 1]),
+            "--gap" => base.gap = parse_dist(&args[i + 1]),
+            "--length" => base.length = parse_dist(&args[i + 1]),
+            "--continue" => base.continue_prob = args[i + 1].parse().expect("Invalid continue probability"),
+            "--spread" => base.spread = args[i + 1].parse().expect("Invalid spread"),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--output" => output_dir = Some(args[i + 1].clone()),
+            _ => panic!("Unknown option: {}\n{}", &args[i], usage),
+        }
+        i += 2;
+    }
+    
+    assert!(num_machines >= 1, "Number of machines must be at least 1");
+    assert!(base.continue_prob >= 0.0 && base.continue_prob <= 1.0, "Continue probability must be between 0 and 1");
+    assert!(base.spread > 1.0, "Spread must be greater than 1, so that machines differ");
+    
+    if let Some(output_dir) = &output_dir {
+        fs::create_dir_all(output_dir).expect("Couldn't create output directory");
+    }
+    
+    // Machines are resampled in the unlikely case that they repeat
+    let mut seen: HashSet<String> = HashSet::new();
+    for m in 0..num_machines {
+        let mut client_machine = generate_machine(&base, &mut rng);
+        while !seen.insert(client_machine.clone()) {
+            client_machine = generate_machine(&base, &mut rng);
+        }
+        let mut relay_machine = generate_machine(&base, &mut rng);
+        while !seen.insert(relay_machine.clone()) {
+            relay_machine = generate_machine(&base, &mut rng);
+        }
+        
+        if let Some(output_dir) = &output_dir {
+            fs::write(format!("{}/client-{}", output_dir, m), format!("{}\n", client_machine)).expect("Couldn't write machine file");
+            fs::write(format!("{}/relay-{}", output_dir, m), format!("{}\n", relay_machine)).expect("Couldn't write machine file");
+        }
+        
+        if num_machines == 1 {
+            println!("Client machine: {} ({})\n", client_machine, client_machine.len());
+            println!("Relay machine: {} ({})\n", relay_machine, relay_machine.len());
+        } else {
+            println!("Client machine {}: {} ({})\n", m, client_machine, client_machine.len());
+            println!("Relay machine {}: {} ({})\n", m, relay_machine, relay_machine.len());
+        }
+    }
+    
+    if let Some(output_dir) = &output_dir {
+        println!("Wrote {} client and relay machines to {}", num_machines, output_dir);
+    }
+}
+
+// Sample a distribution around a base distribution: each parameter is multiplied by a
+// factor drawn log-uniformly from [1 / spread, spread]. Both bounds of uniform
+// distributions share one factor, so that they stay ordered (and equal for constants).
+// The first parameter of log-normal distributions is the mean of the logarithm, so the
+// factor's logarithm is added to it instead (scaling the distribution by the factor).
+// Binomial trials are rounded to whole numbers, and probabilities (of binomial and
+// geometric distributions) are kept at most 1.
+fn sample_dist(base: &Dist, spread: f64, rng: &mut StdRng) -> Dist {
+    let mut dist = base.clone();
+    let log_spread = spread.ln();
+    
+    let factor1 = rng.gen_range(-log_spread, log_spread);
+    let mut factor2 = rng.gen_range(-log_spread, log_spread);
+    match base.dist {
+        DistType::LogNormal => dist.param1 += factor1,
+        DistType::Uniform => {
+            dist.param1 *= factor1.exp();
+            factor2 = factor1;
+        }
+        _ => dist.param1 *= factor1.exp(),
+    }
+    dist.param2 *= factor2.exp();
+    
+    match base.dist {
+        DistType::Geometric => dist.param1 = dist.param1.min(1.0),
+        DistType::Binomial => {
+            dist.param1 = dist.param1.round().max(1.0);
+            dist.param2 = dist.param2.min(1.0);
+        }
+        _ => {}
+    }
+    
+    return dist;
+}
+
+// Generate a randomized machine for one side. The machine works as Maybenot WTF-PAD: after
+// a non-padding cell, BURST sends padding if no other non-padding cell is sent within a
+// timeout, and GAP then sends a fake burst and goes back to BURST (or to START). Unlike
+// WTF-PAD, the timeout and burst length distributions are sampled around the base.
+fn generate_machine(base: &Base, rng: &mut StdRng) -> String {
+    let burst = sample_dist(&base.burst, base.spread, rng);
+    let gap = sample_dist(&base.gap, base.spread, rng);
+    let length = sample_dist(&base.length, base.spread, rng);
+    for (name, dist) in [("burst", &burst), ("gap", &gap), ("length", &length)].iter() {
+        assert!(is_valid_dist(dist), "Sampled an invalid {} distribution ({}, {}, start {}, max {})",
+            name, dist.param1, dist.param2, dist.start, dist.max);
+    }
+    
+    return wtfpad::generate_machine(&burst, &gap, &length, base.continue_prob);
+}
@@ -0,0 +1,330 @@
+// Maybenot Palette -- clusters sites and regularizes each cluster to a shared pattern to approximate the Palette defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::collections::BTreeMap;
+use std::f64::INFINITY;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use maybenot_defenses::trace::{list_files, read_trace, parse_trace_format, site_label, Cell, TraceFormat};
+use maybenot_defenses::machines::{constant_dist, transition_to, generate_start_state, generate_block_state, generate_send_state, generate_recv_state, generate_unblock_state};
+
+use maybenot::{
+machine::Machine,
+state::State
+};
+
+const SLOT: f64             = 100.0; // default, milliseconds per slot of rate profiles
+const NUM_SLOTS: usize      = 100;   // default, slots of rate profiles
+const NUM_BURSTS: usize     = 100;   // default, bursts in each direction of burst profiles
+const SEND_TIMEOUT: f64     = 5.0;   // default, microseconds between cells in a burst
+const MAX_ITERATIONS: usize = 100;   // maximum iterations of k-means
+
+// What the sites are clustered by, and what each cluster is regularized to
+#[derive(Clone, Copy, PartialEq)]
+enum Profile {
+    Rate,   // cells per slot in each direction, sent at a constant rate within each slot
+    Bursts, // size of each burst, alternating from the client, sent as a burst sequence
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() >= 4, "Usage: {} <trace path> <output dir> <num clusters> [--profile rate|bursts] [--slot <ms>] [--slots <slots>] [--bursts <bursts>] [--pacing <usec>] [--seed <seed>] [--trace-format wang|signed]", &args[0]);
+    
+    let trace_path = &args[1];
+    let output_dir = &args[2];
+    let num_clusters: usize = args[3].parse().expect("Invalid number of clusters");
+    
+    let mut profile = Profile::Rate;
+    let mut slot = SLOT * 1000.0;
+    let mut num_slots = NUM_SLOTS;
+    let mut num_bursts = NUM_BURSTS;
+    let mut send_timeout = SEND_TIMEOUT;
+    let mut rng = StdRng::from_entropy(); // randomness for the initial cluster centers
+    let mut trace_format = TraceFormat::Wang;
+    
+    let mut i = 4;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--profile" => profile = match args[i + 1].as_str() {
+                "rate" => Profile::Rate,
+                "bursts" => Profile::Bursts,
+                _ => panic!("Unknown profile: {}", &args[i + 1]),
+            },
+            "--slot" => slot = args[i + 1].parse::<f64>().expect("Invalid slot") * 1000.0,
+            "--slots" => num_slots = args[i + 1].parse().expect("Invalid number of slots"),
+            "--bursts" => num_bursts = args[i + 1].parse().expect("Invalid number of bursts"),
+            "--pacing" => send_timeout = args[i + 1].parse().expect("Invalid pacing"),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--trace-format" => trace_format = parse_trace_format(&args[i + 1]),
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
+    
+    assert!(num_clusters >= 1, "Number of clusters must be at least 1");
+    assert!(profile == Profile::Bursts || trace_format == TraceFormat::Wang, "Rate profiles need timed traces");
+    
+    // Mean profile of each monitored site
+    let mut site_profiles: BTreeMap<usize, (Vec<f64>, usize)> = BTreeMap::new();
+    for trace_file in list_files(&[trace_path.clone()]).iter() {
+        let site = match site_label(trace_file) {
+            Some(site) => site,
+            None => continue,
+        };
+        
+        let cells = read_trace(trace_file, trace_format);
+        let trace_profile = match profile {
+            Profile::Rate => rate_profile(&cells, slot, num_slots),
+            Profile::Bursts => burst_profile(&cells, num_bursts),
+        };
+        
+        let (sum, count) = site_profiles.entry(site).or_insert((vec![0.0; trace_profile.len()], 0));
+        for (total, value) in sum.iter_mut().zip(trace_profile.iter()) {
+            *total += value;
+        }
+        *count += 1;
+    }
+    assert!(site_profiles.len() >= num_clusters, "Fewer monitored sites than clusters");
+    
+    let sites: Vec<usize> = site_profiles.keys().cloned().collect();
+    let profiles: Vec<Vec<f64>> = site_profiles.values()
+        .map(|(sum, count)| sum.iter().map(|total| total / *count as f64).collect())
+        .collect();
+    println!("Loaded {} sites", sites.len());
+    
+    let assignments = cluster(&profiles, num_clusters, &mut rng);
+    
+    fs::create_dir_all(output_dir).expect("Couldn't create output directory");
+    let mapping_file = format!("{}/clusters.csv", output_dir);
+    let file = File::create(&mapping_file).expect("Couldn't create mapping file");
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "site,cluster").expect("Couldn't write mapping file");
+    for (site, cluster) in sites.iter().zip(assignments.iter()) {
+        writeln!(writer, "{},{}", site, cluster).expect("Couldn't write mapping file");
+    }
+    println!("Wrote the cluster of each site to {}", mapping_file);
+    
+    // Each cluster is regularized to the largest profile of its sites, position by position
+    for c in 0..num_clusters {
+        let members: Vec<usize> = (0..profiles.len()).filter(|&s| assignments[s] == c).collect();
+        let mut pattern: Vec<f64> = vec![0.0; profiles[0].len()];
+        for &s in members.iter() {
+            for (max, value) in pattern.iter_mut().zip(profiles[s].iter()) {
+                *max = f64::max(*max, value.ceil());
+            }
+        }
+        
+        let (client_machine, relay_machine) = match profile {
+            Profile::Rate => (
+                generate_rate_machine(&pattern[..num_slots], slot),
+                generate_rate_machine(&pattern[num_slots..], slot),
+            ),
+            Profile::Bursts => (
+                generate_burst_machine(&pattern, false, send_timeout),
+                generate_burst_machine(&pattern, true, send_timeout),
+            ),
+        };
+        
+        fs::write(format!("{}/client-{}", output_dir, c), format!("{}\n", client_machine)).expect("Couldn't write machine file");
+        fs::write(format!("{}/relay-{}", output_dir, c), format!("{}\n", relay_machine)).expect("Couldn't write machine file");
+        
+        println!("Cluster {}: {} sites, {} cells", c, members.len(), pattern.iter().sum::<f64>());
+        println!("Client machine {}: {} ({})\n", c, client_machine, client_machine.len());
+        println!("Relay machine {}: {} ({})\n", c, relay_machine, relay_machine.len());
+    }
+}
+
+// Cells sent by the client in each slot, then cells sent by the relay in each slot. Cells
+// after the last slot are left out.
+fn rate_profile(cells: &[Cell], slot: f64, num_slots: usize) -> Vec<f64> {
+    let mut counts: Vec<f64> = vec![0.0; 2 * num_slots];
+    for cell in cells.iter() {
+        let index = (cell.time / slot) as usize;
+        if index < num_slots {
+            counts[cell.relay_sending as usize * num_slots + index] += 1.0;
+        }
+    }
+    return counts;
+}
+
+// Sizes of the first num_bursts bursts in each direction, alternating from the client (a
+// trace that starts with the relay has an empty first client burst).
+fn burst_profile(cells: &[Cell], num_bursts: usize) -> Vec<f64> {
+    let mut sizes: Vec<f64> = vec![0.0; 2 * num_bursts];
+    let mut index = 0;
+    let mut relay_sending = false;
+    for cell in cells.iter() {
+        if cell.relay_sending != relay_sending {
+            relay_sending = cell.relay_sending;
+            index += 1;
+        }
+        if index >= sizes.len() {
+            break;
+        }
+        sizes[index] += 1.0;
+    }
+    return sizes;
+}
+
+// Cluster profiles into num_clusters clusters by k-means, starting from k-means++ centers.
+// Returns the cluster of each profile.
+fn cluster(profiles: &[Vec<f64>], num_clusters: usize, rng: &mut StdRng) -> Vec<usize> {
+    let distance = |a: &[f64], b: &[f64]| a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum::<f64>();
+    
+    // k-means++: each center is drawn with probability proportional to the squared
+    // distance to the nearest center so far
+    let mut centers: Vec<Vec<f64>> = vec![profiles[rng.gen_range(0, profiles.len())].clone()];
+    while centers.len() < num_clusters {
+        let weights: Vec<f64> = profiles.iter()
+            .map(|profile| centers.iter().map(|center| distance(profile, center)).fold(INFINITY, f64::min))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        
+        let mut next = profiles.len() - 1;
+        if total > 0.0 {
+            let mut target = rng.gen::<f64>() * total;
+            for (p, weight) in weights.iter().enumerate() {
+                if target < *weight {
+                    next = p;
+                    break;
+                }
+                target -= weight;
+            }
+        } else {
+            next = rng.gen_range(0, profiles.len());
+        }
+        centers.push(profiles[next].clone());
+    }
+    
+    let mut assignments: Vec<usize> = vec![0; profiles.len()];
+    for iteration in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (p, profile) in profiles.iter().enumerate() {
+            let mut nearest = 0;
+            for c in 1..num_clusters {
+                if distance(profile, &centers[c]) < distance(profile, &centers[nearest]) {
+                    nearest = c;
+                }
+            }
+            if nearest != assignments[p] {
+                assignments[p] = nearest;
+                changed = true;
+            }
+        }
+        if !changed && iteration > 0 {
+            break;
+        }
+        
+        // Empty clusters keep their center
+        for c in 0..num_clusters {
+            let members: Vec<&Vec<f64>> = profiles.iter().zip(assignments.iter())
+                .filter(|(_, assignment)| **assignment == c)
+                .map(|(profile, _)| profile)
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+            
+            for f in 0..centers[c].len() {
+                centers[c][f] = members.iter().map(|profile| profile[f]).sum::<f64>() / members.len() as f64;
+            }
+        }
+    }
+    
+    return assignments;
+}
+
+// Generate a machine that sends a rate schedule: once there is traffic, all cells are held
+// back, and counts[i] cells are sent evenly over slot i (in place of padding). The machine
+// stops blocking and ends after the last slot with cells.
+fn generate_rate_machine(counts: &[f64], slot: f64) -> String {
+    let num_slots = counts.iter().rposition(|count| *count > 0.0).map_or(0, |last| last + 1);
+    
+    // Set up state vector
+    let num_states = num_slots + 3;
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and BLOCK states
+    states.push(generate_start_state(1, num_states));
+    states.push(generate_block_state(transition_to(2), 0.0, num_states));
+    
+    // SLOT_i states, which wait out slots without cells
+    for i in 0..num_slots {
+        let curr_index = i + 2;
+        if counts[i] > 0.0 {
+            states.push(generate_send_state(counts[i], &constant_dist(slot / counts[i]), curr_index, transition_to(curr_index + 1), num_states));
+        } else {
+            states.push(generate_block_state(transition_to(curr_index + 1), slot, num_states));
+        }
+    }
+    
+    // UNBLOCK state
+    states.push(generate_unblock_state(num_states));
+    
+    return build_machine(states);
+}
+
+// Generate a machine that sends a burst sequence (sizes alternating from the client) for
+// one side: the side sends its bursts with send_timeout between cells, and blocks until it
+// has received the other side's bursts. The machine stops blocking and ends after the last
+// burst.
+fn generate_burst_machine(sizes: &[f64], relay: bool, send_timeout: f64) -> String {
+    // Empty bursts are dropped, merging the bursts around them
+    let mut bursts: Vec<(bool, f64)> = Vec::new();
+    for (b, &size) in sizes.iter().enumerate() {
+        let relay_sending = b % 2 == 1;
+        if size == 0.0 {
+            continue;
+        }
+        match bursts.last_mut() {
+            Some(last) if last.0 == relay_sending => last.1 += size,
+            _ => bursts.push((relay_sending, size)),
+        }
+    }
+    
+    // Set up state vector
+    let num_states = bursts.len() + 3;
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and BLOCK states
+    states.push(generate_start_state(1, num_states));
+    states.push(generate_block_state(transition_to(2), 0.0, num_states));
+    
+    // SEND and RECV states
+    for (b, &(relay_sending, size)) in bursts.iter().enumerate() {
+        let curr_index = b + 2;
+        if relay_sending == relay {
+            states.push(generate_send_state(size, &constant_dist(send_timeout), curr_index, transition_to(curr_index + 1), num_states));
+        } else {
+            states.push(generate_recv_state(size, curr_index, transition_to(curr_index + 1), num_states));
+        }
+    }
+    
+    // UNBLOCK state
+    states.push(generate_unblock_state(num_states));
+    
+    return build_machine(states);
+}
+
+fn build_machine(states: Vec<State>) -> String {
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
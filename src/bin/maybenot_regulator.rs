@@ -5,15 +5,15 @@ use std::env;
 use std::f64::INFINITY;
 use std::collections::HashMap;
 
+use maybenot_defenses::trace::TOR_CELL_SIZE;
+use maybenot_defenses::machines::constant_dist;
+
 use maybenot::{
 machine::Machine,
 event::Event,
-state::State,
-dist::{Dist, DistType}
+state::State
 };
 
-const TOR_CELL_SIZE: f64 = 512.0;
-
 fn main() {
     let args: Vec<String> = env::args().collect();
     assert!(args.len() == 6, "Usage: {} <initial rate> <decay rate> <threshold> <upload ratio> <cells per state>", &args[0]);
@@ -81,21 +81,9 @@ fn generate_client_send_state(num_states: usize) -> State {
     state.bypass = true;
     state.replace = true;
     
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 0.0,
-        param2: 0.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.timeout = constant_dist(0.0);
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: TOR_CELL_SIZE,
-        param2: TOR_CELL_SIZE,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(TOR_CELL_SIZE);
     
     return state;
 }
@@ -133,29 +121,11 @@ fn generate_client_count_state(curr_index: usize, next_index: usize, num_states:
     state.bypass = true;
     state.replace = true;
     
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 0.0,
-        param2: 0.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.timeout = constant_dist(0.0);
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: INFINITY,
-        param2: INFINITY,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(INFINITY);
     
-    state.limit = Dist {
-        dist: DistType::Uniform,
-        param1: 2.0,
-        param2: 2.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.limit = constant_dist(2.0);
     
     return state;
 }
@@ -263,29 +233,11 @@ fn generate_relay_send_state(curr_index: usize, next_index: usize, num_states: u
     state.bypass = true;
     state.replace = true;
     
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: timeout,
-        param2: timeout,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.timeout = constant_dist(timeout);
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: TOR_CELL_SIZE,
-        param2: TOR_CELL_SIZE,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(TOR_CELL_SIZE);
     
-    state.limit = Dist {
-        dist: DistType::Uniform,
-        param1: padding_count,
-        param2: padding_count,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.limit = constant_dist(padding_count);
     
     return state;
 }
@@ -310,21 +262,9 @@ fn generate_relay_boot_state(curr_index: usize, next_index: usize, num_states: u
     state.bypass = true;
     state.replace = true;
     
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: timeout,
-        param2: timeout,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.timeout = constant_dist(timeout);
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: TOR_CELL_SIZE,
-        param2: TOR_CELL_SIZE,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(TOR_CELL_SIZE);
     
     return state;
 }
@@ -345,21 +285,9 @@ fn generate_relay_block_state(num_states: usize) -> State {
     state.bypass = true;
     state.replace = true;
     
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 0.0,
-        param2: 0.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.timeout = constant_dist(0.0);
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: INFINITY,
-        param2: INFINITY,
-        start: 0.0,
-        max: 0.0,
-    };
+    state.action = constant_dist(INFINITY);
     
     return state;
 }
@@ -2,252 +2,402 @@
 // Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
 
 use std::env;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::f64::INFINITY;
 
-use maybenot::{
-machine::Machine,
-event::Event,
-state::State,
-dist::{Dist, DistType}
-};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-const TOR_CELL_SIZE: f64   = 512.0;
-const CUTOFF_LENGTH: usize = 8000; // bursts
+use maybenot_defenses::trace::{list_files, parse_trace_format, TraceFormat};
+use maybenot_defenses::surakav::{default_options, parse_pacing, parse_tail, dist_max, read_lines, read_cell_trace, write_reference, exit_on_error, merge_small_bursts, fits_budget, report_truncation, build_machines, Burst, ReferenceError};
+use maybenot_defenses::stats::{sample_standard_normal, mean_stdev};
+
+const MIN_SAMPLES: usize   = 10;      // burst sizes needed to fit a position of the log-normal model
+const NUM_BINS: usize      = 33;      // power-of-two burst size bins of the Markov model
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    assert!(args.len() == 2, "Usage: {} <ref trace path>", &args[0]);
+    if args.len() >= 2 && args[1] == "gen-refs" {
+        generate_references_main(&args);
+        return;
+    }
+    if args.len() >= 2 && args[1] == "extract" {
+        extract_references_main(&args);
+        return;
+    }
     
-    let (client_machine, relay_machine) = parse_file(&args[1]);
-    println!("Client machine: {} ({})\n", client_machine, client_machine.len());
-    println!("Relay machine: {} ({})\n", relay_machine, relay_machine.len());
-}
-
-// Takes reference traces and returns Surakav client and relay machines
-fn parse_file(burst_file: &str) -> (String, String) {
-    let (lines, num_bursts) = read_lines(burst_file);
+    assert!(args.len() >= 2, "Usage: {} <ref trace path>... [--cutoff <bursts>] [--pacing <spec>] [--weights <w1,w2,...>] [--sync-timeout <ms>] [--tail <spec>] [--seed <seed>] [--headroom <trace path>] [--jitter <percent>] [--quantize <base>] [--min-burst <cells>] [--max-states <states>] [--max-bytes <bytes>] [--trace-format wang|signed] [--burst-gap <ms>]\n       {} gen-refs <trace dir> <output dir> <num references> [--model lognormal|markov] [--seed <seed>] [--trace-format wang|signed] [--burst-gap <ms>]\n       {} extract <trace path> <output path> [--trace-format wang|signed] [--burst-gap <ms>]", &args[0], &args[0], &args[0]);
     
-    // Machine info
-    let num_states = num_bursts + 2;
+    let mut options = default_options();
     
-    // Generate states
-    let mut relay_states: Vec<State> = Vec::with_capacity(num_states);
-    let mut client_states: Vec<State> = Vec::with_capacity(num_states);
+    let mut ref_paths: Vec<String> = Vec::new();      // reference trace files or directories
+    let mut headroom_paths: Vec<String> = Vec::new(); // undefended traces to compare reference lengths against
+    let mut rng = StdRng::from_entropy();             // randomness for synthetic tails and burst size noise
     
-    relay_states.push(generate_start_state(1, num_states));
-    relay_states.push(generate_block_state(2, num_states));
+    let mut i = 1;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            ref_paths.push(args[i].clone());
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--cutoff" => options.cutoff_length = args[i + 1].parse().expect("Invalid cutoff"),
+            "--pacing" => options.send_timeout = parse_pacing(&args[i + 1]),
+            "--weights" => options.weights = args[i + 1].split(',').map(|w| w.parse().expect("Invalid weight")).collect(),
+            "--sync-timeout" => options.sync_timeout = args[i + 1].parse::<f64>().expect("Invalid sync timeout") * 1000.0,
+            "--tail" => options.tail = parse_tail(&args[i + 1]),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--headroom" => headroom_paths.push(args[i + 1].clone()),
+            "--jitter" => options.noise.jitter = args[i + 1].parse::<f64>().expect("Invalid jitter") / 100.0,
+            "--quantize" => options.noise.quantize = args[i + 1].parse().expect("Invalid quantization base"),
+            "--min-burst" => options.min_burst = args[i + 1].parse().expect("Invalid min burst"),
+            "--max-states" => options.max_states = args[i + 1].parse().expect("Invalid max states"),
+            "--max-bytes" => options.max_bytes = args[i + 1].parse().expect("Invalid max bytes"),
+            "--trace-format" => options.trace_format = Some(parse_trace_format(&args[i + 1])),
+            "--burst-gap" => options.burst_gap = args[i + 1].parse::<f64>().expect("Invalid burst gap") * 1000.0,
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
     
-    client_states.push(generate_start_state(1, num_states));
-    client_states.push(generate_block_state(2, num_states));
+    // Number of bursts in each undefended trace, for the headroom report
+    let trace_format = options.trace_format.unwrap_or(TraceFormat::Wang);
+    let trace_lengths: Vec<usize> = list_files(&headroom_paths).iter()
+        .map(|trace_file| exit_on_error(read_cell_trace(trace_file, trace_format, options.burst_gap)).0.len())
+        .collect();
     
-    // After START + BLOCK states:
-    // client   -->  SEND  --> (RECV) --> ... --> StateEnd
-    // relay    --> (RECV) -->  SEND  --> ... --> StateEnd
-    let mut curr_idx: usize = 2;
-    let mut next_idx: usize = 3;
-    let mut relay_sending = false;
-
-    for lines_idx in 0..(lines.len()) {
-        if lines[lines_idx] == 0 {
-            relay_sending = !relay_sending;
-            continue;
+    assert!(options.noise.jitter >= 0.0 && options.noise.jitter < 1.0, "Jitter must be at least 0% and below 100%");
+    
+    // Bursts at divergence points are decoded by the silence after them, so the time
+    // between their cells must stay well below the sync timeout
+    assert!(options.sync_timeout > 0.0, "Sync timeout must be positive");
+    match dist_max(&options.send_timeout) {
+        Some(max_pacing) => assert!(max_pacing <= options.sync_timeout / 2.0,
+            "Send pacing of up to {} us is not well below the sync timeout ({} us): it may be at most half of it", max_pacing, options.sync_timeout),
+        None => panic!("Send pacing must be bounded, with a <max> of at most half the sync timeout ({} us)", options.sync_timeout),
+    }
+    assert!(options.noise.quantize == 0.0 || options.noise.quantize > 1.0, "Quantization base must be greater than 1");
+    
+    let ref_files = list_files(&ref_paths);
+    assert!(!ref_files.is_empty(), "No reference traces given");
+    if options.weights.is_empty() {
+        options.weights = vec![1.0; ref_files.len()];
+    }
+    assert!(options.weights.len() == ref_files.len(), "Expected {} weights, one per reference trace", ref_files.len());
+    assert!(options.weights.iter().all(|w| *w > 0.0), "Weights must be positive");
+    
+    // Read references, merging small bursts
+    let mut references: Vec<(String, Vec<Burst>, bool)> = Vec::with_capacity(ref_files.len());
+    for ref_file in ref_files.iter() {
+        let (mut bursts, timed) = exit_on_error(match options.trace_format {
+            Some(format) => read_cell_trace(ref_file, format, options.burst_gap),
+            None => read_lines(ref_file, options.cutoff_length),
+        });
+        if bursts.len() > options.cutoff_length {
+            eprintln!("Warning: {}: truncated to {} bursts, dropping {} bursts", ref_file, options.cutoff_length, bursts.len() - options.cutoff_length);
+            bursts.truncate(options.cutoff_length);
         }
         
-        let (send_state, recv_state) = generate_burst_states(lines[lines_idx] as f64, curr_idx, next_idx, num_states);
+        let relay_bursts = bursts.iter().filter(|burst| burst.relay_sending).count();
+        println!("Reference {}: loaded {} bursts ({} client, {} relay)", ref_file, bursts.len(), bursts.len() - relay_bursts, relay_bursts);
         
-        if relay_sending {
-            relay_states.push(send_state);
-            client_states.push(recv_state);
-        } else {
-            relay_states.push(recv_state);
-            client_states.push(send_state);
+        if options.min_burst > 0 {
+            let num_bursts = bursts.len();
+            let (merged, moved_cells) = merge_small_bursts(&bursts, options.min_burst);
+            println!("Reference {}: merged {} bursts into {} ({} cells moved to a later burst, past bursts in the other direction)", ref_file, num_bursts, merged.len(), moved_cells);
+            bursts = merged;
         }
         
-        curr_idx += 1;
-        next_idx += 1;
-        if next_idx == num_states {
-            next_idx = num_states + 1; // StateEnd
-        }
-        relay_sending = !relay_sending;
+        references.push((ref_file.clone(), bursts, timed));
     }
     
-    // Generate machine
-    let relay_machine = Machine {
-        allowed_padding_bytes: u64::MAX,
-        max_padding_frac: 0.0,
-        allowed_blocked_microsec: u64::MAX,
-        max_blocking_frac: 0.0,
-        states: relay_states,
-        include_small_packets: false,
-    };
-    let client_machine = Machine {
-        allowed_padding_bytes: u64::MAX,
-        max_padding_frac: 0.0,
-        allowed_blocked_microsec: u64::MAX,
-        max_blocking_frac: 0.0,
-        states: client_states,
-        include_small_packets: false,
-    };
+    // Truncate references to fit the state and size budget
+    let longest = references.iter().map(|(_, bursts, _)| bursts.len()).max().unwrap();
+    let mut max_bursts = longest;
+    if !fits_budget(&references, max_bursts, &options, &rng) {
+        let mut low = 0;
+        let mut high = longest;
+        while low < high {
+            let mid = (low + high + 1) / 2;
+            if fits_budget(&references, mid, &options, &rng) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        
+        // Even the START and BLOCK states (and tails) may not fit
+        if !fits_budget(&references, low, &options, &rng) {
+            eprintln!("Error: the machines do not fit --max-states {} / --max-bytes {}, even with no reference bursts", options.max_states, options.max_bytes);
+            process::exit(1);
+        }
+        max_bursts = low;
+        report_truncation(&references, max_bursts);
+    }
     
-    return (relay_machine.serialize(), client_machine.serialize());
+    let (client_machine, relay_machine) = build_machines(&references, max_bursts, &options, &mut rng.clone(), Some(trace_lengths.as_slice()));
+    let client_machine = client_machine.serialize();
+    let relay_machine = relay_machine.serialize();
+    println!("Client machine: {} ({})\n", client_machine, client_machine.len());
+    println!("Relay machine: {} ({})\n", relay_machine, relay_machine.len());
 }
 
-// Generate a START state. This is used as the initial state in a machine.
-fn generate_start_state(next_index: usize, num_states: usize) -> State {
-    let mut nonpadding_sent: HashMap<usize, f64> = HashMap::new();
-    nonpadding_sent.insert(next_index, 1.0);
+// Sample synthetic reference traces from a burst model fitted to undefended traces.
+fn generate_references_main(args: &[String]) {
+    assert!(args.len() >= 5, "Usage: {} gen-refs <trace dir> <output dir> <num references> [--model lognormal|markov] [--seed <seed>] [--trace-format wang|signed] [--burst-gap <ms>]", &args[0]);
     
-    let mut nonpadding_recv: HashMap<usize, f64> = HashMap::new();
-    nonpadding_recv.insert(next_index, 1.0);
+    let trace_dir = &args[2];
+    let output_dir = &args[3];
+    let num_references: usize = args[4].parse().expect("Invalid num references");
     
-    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
-    transitions.insert(Event::NonPaddingSent, nonpadding_sent);
-    transitions.insert(Event::NonPaddingRecv, nonpadding_recv);
+    let mut model = String::from("lognormal"); // burst model: lognormal or markov
+    let mut rng = StdRng::from_entropy();
+    let mut trace_format = TraceFormat::Wang;
+    let mut burst_gap = INFINITY;
     
-    return State::new(transitions, num_states);
-}
-
-// Generate a BLOCK state. This is used to enable infinite blocking after the START state.
-fn generate_block_state(next_index: usize, num_states: usize) -> State {
-    // Transitions
-    let mut blocking_begin: HashMap<usize, f64> = HashMap::new();
-    blocking_begin.insert(next_index, 1.0);
-    
-    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
-    transitions.insert(Event::BlockingBegin, blocking_begin);
-    
-    let mut state = State::new(transitions, num_states);
-    state.action_is_block = true;
-    state.bypass = true;
-    state.replace = true;
-    
-    state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 0.0,
-        param2: 0.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    let mut i = 5;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--model" => model = args[i + 1].clone(),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--trace-format" => trace_format = parse_trace_format(&args[i + 1]),
+            "--burst-gap" => burst_gap = args[i + 1].parse::<f64>().expect("Invalid burst gap") * 1000.0,
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
     
-    state.action = Dist {
-        dist: DistType::Uniform,
-        param1: INFINITY,
-        param2: INFINITY,
-        start: 0.0,
-        max: 0.0,
-    };
+    let mut traces: Vec<Vec<Burst>> = Vec::new();
+    for trace_file in list_files(&[trace_dir.clone()]).iter() {
+        let (bursts, _) = exit_on_error(read_cell_trace(trace_file, trace_format, burst_gap));
+        if !bursts.is_empty() {
+            traces.push(bursts);
+        }
+    }
+    assert!(!traces.is_empty(), "No traces found in {}", trace_dir);
     
-    return state;
-}
-
-fn generate_burst_states(num_cells: f64, curr_index: usize, next_index: usize, num_states: usize) -> (State, State) {
-    // Transitions
-    let mut limit_reached_send: HashMap<usize, f64> = HashMap::new();
-    let mut limit_reached_recv: HashMap<usize, f64> = HashMap::new();
-    limit_reached_send.insert(next_index, 1.0);
-    limit_reached_recv.insert(next_index, 1.0);
+    exit_on_error(create_dir(output_dir));
     
-    let mut padding_sent: HashMap<usize, f64> = HashMap::new();
-    padding_sent.insert(curr_index, 1.0);
+    match model.as_str() {
+        "lognormal" => {
+            let fitted = fit_lognormal_model(&traces);
+            for j in 0..num_references {
+                let bursts = sample_lognormal_model(&fitted, &mut rng);
+                exit_on_error(write_reference(&format!("{}/ref-{}.txt", output_dir, j), &bursts, false));
+            }
+        }
+        "markov" => {
+            let fitted = fit_markov_model(&traces);
+            for j in 0..num_references {
+                let bursts = sample_markov_model(&fitted, &mut rng);
+                exit_on_error(write_reference(&format!("{}/ref-{}.txt", output_dir, j), &bursts, false));
+            }
+        }
+        _ => panic!("Unknown model: {}", model),
+    }
     
-    let mut nonpadding_recv: HashMap<usize, f64> = HashMap::new();
-    nonpadding_recv.insert(curr_index, 1.0);
+    println!("Wrote {} references to {} ({} model, {} traces)", num_references, output_dir, model, traces.len());
+}
+
+// Write reference traces extracted from cell-level traces.
+fn extract_references_main(args: &[String]) {
+    assert!(args.len() >= 4, "Usage: {} extract <trace path> <output path> [--trace-format wang|signed] [--burst-gap <ms>]", &args[0]);
     
-    let mut padding_recv: HashMap<usize, f64> = HashMap::new();
-    padding_recv.insert(curr_index, 1.0);
+    let trace_path = &args[2];
+    let output_path = &args[3];
     
-    let mut transitions_send: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
-    let mut transitions_recv: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    let mut trace_format = TraceFormat::Wang;
+    let mut burst_gap = INFINITY;
     
-    transitions_send.insert(Event::LimitReached, limit_reached_send);
-    transitions_send.insert(Event::PaddingSent, padding_sent);
+    let mut i = 4;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--trace-format" => trace_format = parse_trace_format(&args[i + 1]),
+            "--burst-gap" => burst_gap = args[i + 1].parse::<f64>().expect("Invalid burst gap") * 1000.0,
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
     
-    transitions_recv.insert(Event::LimitReached, limit_reached_recv);
-    transitions_recv.insert(Event::NonPaddingRecv, nonpadding_recv);
-    transitions_recv.insert(Event::PaddingRecv, padding_recv);
+    // A directory of traces is written to a directory of references with the same names
+    if !Path::new(trace_path).is_dir() {
+        let (bursts, timed) = exit_on_error(read_cell_trace(trace_path, trace_format, burst_gap));
+        exit_on_error(write_reference(output_path, &bursts, timed));
+        println!("Wrote {} bursts to {}", bursts.len(), output_path);
+        return;
+    }
     
-    // States
-    let mut send_state = State::new(transitions_send, num_states);
-    let mut recv_state = State::new(transitions_recv, num_states);
+    exit_on_error(create_dir(output_path));
+    let trace_files = list_files(&[trace_path.clone()]);
+    for trace_file in trace_files.iter() {
+        let (bursts, timed) = exit_on_error(read_cell_trace(trace_file, trace_format, burst_gap));
+        let name = Path::new(trace_file).file_name().unwrap().to_string_lossy().into_owned();
+        exit_on_error(write_reference(&format!("{}/{}", output_path, name), &bursts, timed));
+    }
+    println!("Wrote {} references to {}", trace_files.len(), output_path);
+}
+
+// Create an output directory, with any missing parents.
+fn create_dir(path: &str) -> Result<(), ReferenceError> {
+    return fs::create_dir_all(path).map_err(|err| ReferenceError::Io(path.to_string(), err));
+}
+
+// Log-normal burst size model: the log of the size of the k-th burst in each
+// direction is normally distributed. Positions with too few samples fall back to
+// the pooled distribution of the direction.
+struct LogNormalModel {
+    first_relay: f64,            // probability that the relay sends the first burst
+    lengths: Vec<usize>,         // number of bursts in each training trace
+    params: Vec<[(f64, f64); 2]>, // per position, (mean, stdev) of the log size for client and relay bursts
+}
+
+fn fit_lognormal_model(traces: &[Vec<Burst>]) -> LogNormalModel {
+    let max_length = traces.iter().map(|bursts| bursts.len()).max().unwrap();
     
-    send_state.bypass = true;
-    send_state.replace = true;
+    let mut samples: Vec<[Vec<f64>; 2]> = (0..max_length).map(|_| [Vec::new(), Vec::new()]).collect();
+    let mut pooled: [Vec<f64>; 2] = [Vec::new(), Vec::new()];
+    let mut first_relay = 0.0;
     
-    recv_state.action_is_block = true;
-    recv_state.bypass = true;
-    recv_state.replace = true;
+    for bursts in traces.iter() {
+        if bursts[0].relay_sending {
+            first_relay += 1.0;
+        }
+        for (k, burst) in bursts.iter().enumerate() {
+            let log_size = (burst.num_cells as f64).ln();
+            samples[k][burst.relay_sending as usize].push(log_size);
+            pooled[burst.relay_sending as usize].push(log_size);
+        }
+    }
     
-    send_state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 5.0,
-        param2: 5.0,
-        start: 0.0,
-        max: 0.0,
-    };
-    recv_state.timeout = Dist {
-        dist: DistType::Uniform,
-        param1: 0.0,
-        param2: 0.0,
-        start: 0.0,
-        max: 0.0,
-    };
+    let pooled_params = [mean_stdev(&pooled[0]), mean_stdev(&pooled[1])];
+    let mut params: Vec<[(f64, f64); 2]> = Vec::with_capacity(max_length);
+    for position in samples.iter() {
+        let mut position_params = pooled_params;
+        for direction in 0..2 {
+            if position[direction].len() >= MIN_SAMPLES {
+                position_params[direction] = mean_stdev(&position[direction]);
+            }
+        }
+        params.push(position_params);
+    }
     
-    send_state.action = Dist {
-        dist: DistType::Uniform,
-        param1: TOR_CELL_SIZE,
-        param2: TOR_CELL_SIZE,
-        start: 0.0,
-        max: 0.0,
-    };
-    recv_state.action = Dist {
-        dist: DistType::Uniform,
-        param1: INFINITY,
-        param2: INFINITY,
-        start: 0.0,
-        max: 0.0,
+    return LogNormalModel {
+        first_relay: first_relay / (traces.len() as f64),
+        lengths: traces.iter().map(|bursts| bursts.len()).collect(),
+        params: params,
     };
+}
+
+fn sample_lognormal_model(model: &LogNormalModel, rng: &mut StdRng) -> Vec<Burst> {
+    let length = model.lengths[rng.gen_range(0, model.lengths.len())];
+    let mut relay_sending = rng.gen::<f64>() < model.first_relay;
+    let mut bursts: Vec<Burst> = Vec::with_capacity(length);
     
-    send_state.limit = Dist {
-        dist: DistType::Uniform,
-        param1: num_cells,
-        param2: num_cells,
-        start: 0.0,
-        max: 0.0,
-    };
-    recv_state.limit = Dist {
-        dist: DistType::Uniform,
-        param1: num_cells,
-        param2: num_cells,
-        start: 0.0,
-        max: 0.0,
-    };
+    for k in 0..length {
+        let (mean, stdev) = model.params[k][relay_sending as usize];
+        let num_cells = (mean + stdev * sample_standard_normal(rng)).exp().round().max(1.0);
+        
+        bursts.push(Burst {
+            relay_sending: relay_sending,
+            num_cells: num_cells as usize,
+            start: 0.0,
+            duration: 0.0,
+        });
+        relay_sending = !relay_sending;
+    }
     
-    return (send_state, recv_state);
+    return bursts;
 }
 
-fn read_lines(filename: &str) -> (Vec<usize>, usize) {
-    let file = File::open(filename).expect("Couldn't open trace file");
-    let reader = BufReader::new(file);
-
-    let mut lines: Vec<usize> = Vec::new();
-    let mut count: usize = 0;
+// Markov burst size model: burst sizes are quantized to power-of-two bins, and the
+// direction and bin of each burst depend on the direction and bin of the previous one
+// (so that consecutive bursts in the same direction, from --burst-gap, are kept). Sizes
+// are drawn from the sizes observed in the chosen direction and bin.
+struct MarkovModel {
+    initial: Vec<f64>,          // counts of the first burst's direction and bin
+    transitions: Vec<Vec<f64>>, // per direction and bin, counts of the next burst's (the last entry ends the trace)
+    sizes: Vec<Vec<usize>>,     // per direction and bin, the burst sizes observed
+}
 
-    for (_, line) in reader.lines().enumerate() {
-        if count >= CUTOFF_LENGTH {
-            break;
-        }
+fn fit_markov_model(traces: &[Vec<Burst>]) -> MarkovModel {
+    let mut model = MarkovModel {
+        initial: vec![0.0; 2 * NUM_BINS],
+        transitions: vec![vec![0.0; 2 * NUM_BINS + 1]; 2 * NUM_BINS],
+        sizes: vec![Vec::new(); 2 * NUM_BINS],
+    };
+    
+    for bursts in traces.iter() {
+        model.initial[markov_state(&bursts[0])] += 1.0;
         
-        if let Ok(ip) = line {
-            let val: u32 = ip.parse().expect("Line not formatted properly");
-            lines.push(val as usize);
-            if val != 0 {
-                count += 1;
+        for (k, burst) in bursts.iter().enumerate() {
+            let state = markov_state(burst);
+            model.sizes[state].push(burst.num_cells);
+            
+            let mut next = 2 * NUM_BINS; // end of trace
+            if k + 1 < bursts.len() {
+                next = markov_state(&bursts[k + 1]);
             }
+            model.transitions[state][next] += 1.0;
+        }
+    }
+    
+    return model;
+}
+
+fn sample_markov_model(model: &MarkovModel, rng: &mut StdRng) -> Vec<Burst> {
+    let mut state = sample_index(&model.initial, rng);
+    let mut bursts: Vec<Burst> = Vec::new();
+    
+    // Only states that were observed have counts, but a state without sizes ends the trace
+    // rather than drawing from an empty bin
+    while bursts.len() < CUTOFF_LENGTH && !model.sizes[state].is_empty() {
+        let sizes = &model.sizes[state];
+        bursts.push(Burst {
+            relay_sending: state >= NUM_BINS,
+            num_cells: sizes[rng.gen_range(0, sizes.len())],
+            start: 0.0,
+            duration: 0.0,
+        });
+        
+        let next = sample_index(&model.transitions[state], rng);
+        if next == 2 * NUM_BINS {
+            break;
         }
+        state = next;
     }
+    
+    return bursts;
+}
+
+// Markov model state of a burst: its direction and size bin
+fn markov_state(burst: &Burst) -> usize {
+    return (burst.relay_sending as usize) * NUM_BINS + size_bin(burst.num_cells);
+}
 
-    return (lines, count);
+// Power-of-two bin of a burst size (the last bin is open-ended)
+fn size_bin(num_cells: usize) -> usize {
+    let bin = (num_cells.max(1) as f64).log2().floor() as usize;
+    return bin.min(NUM_BINS - 1);
+}
+
+// Sample an index with probability proportional to its count
+fn sample_index(counts: &[f64], rng: &mut StdRng) -> usize {
+    let total: f64 = counts.iter().sum();
+    let mut remaining = rng.gen::<f64>() * total;
+    
+    for (idx, count) in counts.iter().enumerate() {
+        if remaining < *count {
+            return idx;
+        }
+        remaining -= count;
+    }
+    
+    return counts.iter().rposition(|count| *count > 0.0).unwrap();
 }
@@ -0,0 +1,81 @@
+// Maybenot Tamaraw -- uses constant-rate traffic, padded to a multiple of L cells, to approximate the Tamaraw defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+
+use maybenot_defenses::machines::{constant_dist, transition_to, generate_start_state, generate_block_state, generate_cell_state, generate_unblock_state};
+
+use maybenot::{
+machine::Machine,
+state::State
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() == 4, "Usage: {} <client interval> <relay interval> <padding multiple>", &args[0]);
+    
+    let client_interval:  f64   = args[1].parse().expect("Invalid client interval");  // Tamaraw param = rho_out, time between client cells (ms)
+    let relay_interval:   f64   = args[2].parse().expect("Invalid relay interval");   // Tamaraw param = rho_in, time between relay cells (ms)
+    let padding_multiple: usize = args[3].parse().expect("Invalid padding multiple"); // Tamaraw param = L, cells sent are padded to a multiple of L
+    assert!(padding_multiple >= 1, "Padding multiple must be at least 1");
+    
+    let client_machine = generate_machine(client_interval * 1000.0, padding_multiple);
+    println!("Client machine: {} ({})", client_machine, client_machine.len());
+    println!();
+    
+    let relay_machine = generate_machine(relay_interval * 1000.0, padding_multiple);
+    println!("Relay machine: {} ({})", relay_machine, relay_machine.len());
+    println!();
+}
+
+// Generate a Tamaraw machine for one side. Once there is traffic, all cells are held back
+// and sent at a constant rate, in place of padding. The SEND_i and DATA_i states count cells
+// modulo L: SEND_i sends cell i of the current cycle of L cells, and moves to DATA_i if a
+// non-padding cell took its place. The last cell of a cycle going out as padding means no
+// non-padding cell was waiting, so the page is done, and since the cycle ends at a multiple
+// of L cells, the machine stops blocking and ends there.
+fn generate_machine(interval: f64, padding_multiple: usize) -> String {
+    // Set up state vector
+    let num_states = 2 * padding_multiple + 3;
+    let send_index = 2;
+    let data_index = send_index + padding_multiple;
+    let unblock_index = data_index + padding_multiple;
+    let timeout = constant_dist(interval);
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START and BLOCK states (cells sent before blocking begins are not counted)
+    states.push(generate_start_state(1, num_states));
+    states.push(generate_block_state(transition_to(send_index), 0.0, num_states));
+    
+    // SEND_i states (padding in the last cell of the cycle moves to UNBLOCK)
+    for i in 0..padding_multiple {
+        let mut next_index = send_index + i + 1;
+        if i == padding_multiple - 1 {
+            next_index = unblock_index;
+        }
+        
+        states.push(generate_cell_state(Some(data_index + i), next_index, &timeout, num_states));
+    }
+    
+    // DATA_i states (a non-padding last cell starts the next cycle)
+    for i in 0..padding_multiple {
+        let next_index = send_index + (i + 1) % padding_multiple;
+        states.push(generate_cell_state(None, next_index, &timeout, num_states));
+    }
+    
+    // UNBLOCK state
+    states.push(generate_unblock_state(num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
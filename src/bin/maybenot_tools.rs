@@ -0,0 +1,776 @@
+// Maybenot tools -- evaluates generated machines over cell-level traces
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+
+use maybenot::machine::Machine;
+
+use maybenot_defenses::trace::{list_files, read_trace, write_trace, parse_trace_format, site_label, Cell, TraceFormat};
+use maybenot_defenses::simulator::{parse_machines, simulate, SimConfig};
+use maybenot_defenses::overhead::{measure, summarize, Stats, Summary};
+use maybenot_defenses::generate::{find_defense, generate, parse_values, tune, Defense};
+use maybenot_defenses::features::{knn_features, extract_features, parse_feature_set, FeatureSet};
+use maybenot_defenses::knn::{learn_weights, classify_all};
+use maybenot_defenses::leakage::leakage_all;
+
+const DELAY: f64  = 25.0; // default, milliseconds of one-way delay between client and relay
+const LINGER: f64 = 10.0; // default, seconds simulated after the last cell of a trace
+const FOLDS: usize  = 10;   // default, cross-validation folds of the kNN attack
+const K: usize      = 5;    // default, neighbors of the kNN attack
+const LEARN: usize  = 1000; // default, training points used to learn kNN weights
+const BATCH: usize  = 256;  // traces per thread between writes of the feature file
+const GRID: usize   = 1000; // default, grid points of the leakage density estimates
+const TOP: usize    = 20;   // default, features with the most leakage that are printed
+
+const SEED_NOTE: &str = "  --seed does not make simulations reproducible: it fixes only the network jitter, as Maybenot samples the machines' timeouts, limits and actions with its own RNG, which cannot be seeded";
+
+// A point of a parameter sweep: the generated machines, and their overhead if measured
+struct SweepRow {
+    values: Vec<f64>,
+    client_bytes: usize,
+    client_states: usize,
+    relay_bytes: usize,
+    relay_states: usize,
+    summary: Option<Summary>,
+}
+
+// Options shared by the subcommands that simulate machines
+struct SimOptions {
+    config: SimConfig,
+    rng: StdRng,
+    trace_format: TraceFormat,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = format!("Usage: {} simulate <client machines> <relay machines> <trace path> <output path> [--delay <ms>] [--jitter <ms>] [--linger <sec>] [--seed <seed>] [--trace-format wang|signed]\n       {} overhead <client machines> <relay machines> <trace path> [--delay <ms>] [--jitter <ms>] [--linger <sec>] [--seed <seed>] [--trace-format wang|signed]\n       {} sweep <defense> <param>=<values>... [--traces <trace path>] [--output <file>] [--threads <threads>] [simulation options]\n       {} tune <defense> <param>=<low>:<high> <param>=<value>... --traces <trace path> --target <percent> [--metric client|relay|total|latency] [--stat aggregate|mean|median] [--resolution <step>] [simulation options]\n       {} evaluate <trace path> [--open-world] [--folds <folds>] [--k <neighbors>] [--learn <points>] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]\n       {} features <trace path> <output file> [--set knn|cumul|kfp|bursts,...] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]\n       {} leakage <trace path> [--set knn|cumul|kfp|bursts,...] [--grid <points>] [--top <features>] [--output <file>] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]\n{}", &args[0], &args[0], &args[0], &args[0], &args[0], &args[0], &args[0], SEED_NOTE);
+    assert!(args.len() >= 2, "{}", usage);
+    
+    match args[1].as_str() {
+        "simulate" => simulate_main(&args),
+        "overhead" => overhead_main(&args),
+        "sweep" => sweep_main(&args),
+        "tune" => tune_main(&args),
+        "evaluate" => evaluate_main(&args),
+        "features" => features_main(&args),
+        "leakage" => leakage_main(&args),
+        _ => panic!("{}", usage),
+    }
+}
+
+// Run a client/relay machine pair over an undefended trace, or a directory of them, and
+// write the defended traces (Wang format) from the client's point of view. Machines are
+// given as "none", serialized machines separated by commas, or a file containing them.
+fn simulate_main(args: &[String]) {
+    assert!(args.len() >= 6, "Usage: {} simulate <client machines> <relay machines> <trace path> <output path> [--delay <ms>] [--jitter <ms>] [--linger <sec>] [--seed <seed>] [--trace-format wang|signed]\n{}", &args[0], SEED_NOTE);
+    
+    let client_machines = parse_machines(&args[2]);
+    let relay_machines = parse_machines(&args[3]);
+    let trace_path = &args[4];
+    let output_path = &args[5];
+    
+    let sim_options = parse_sim_options(&args[6..]);
+    let config = sim_options.config;
+    let mut rng = sim_options.rng;
+    
+    // A directory of traces is written to a directory of defended traces with the same names
+    let mut outputs: Vec<(String, String)> = Vec::new();
+    if Path::new(trace_path).is_dir() {
+        fs::create_dir_all(output_path).expect("Couldn't create output directory");
+        for trace_file in list_files(&[trace_path.clone()]) {
+            let name = Path::new(&trace_file).file_name().unwrap().to_string_lossy().into_owned();
+            outputs.push((trace_file, format!("{}/{}", output_path, name)));
+        }
+    } else {
+        outputs.push((trace_path.clone(), output_path.clone()));
+    }
+    
+    for (trace_file, output_file) in outputs.iter() {
+        let trace = read_trace(trace_file, sim_options.trace_format);
+        let simulation = simulate(&client_machines, &relay_machines, &trace, config, &mut rng);
+        if let Err(err) = write_trace(output_file, &simulation.trace()) {
+            eprintln!("Couldn't write defended trace: {}", err);
+            process::exit(1);
+        }
+        
+        if simulation.undelivered > 0 {
+            println!("Warning: {}: {} non-padding cells were not delivered (blocked or past the linger time)", trace_file, simulation.undelivered);
+        }
+    }
+    println!("Wrote {} defended traces to {}", outputs.len(), output_path);
+}
+
+// Parse the simulation options (--delay, --jitter, --linger, --seed, --trace-format).
+fn parse_sim_options(args: &[String]) -> SimOptions {
+    let mut config = SimConfig {
+        delay: DELAY * 1000.0,
+        jitter: 0.0,
+        linger: LINGER * 1000000.0,
+    };
+    let mut seed: Option<u64> = None;
+    let mut trace_format = TraceFormat::Wang;
+    
+    let mut i = 0;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--delay" => config.delay = args[i + 1].parse::<f64>().expect("Invalid delay") * 1000.0,
+            "--jitter" => config.jitter = args[i + 1].parse::<f64>().expect("Invalid jitter") * 1000.0,
+            "--linger" => config.linger = args[i + 1].parse::<f64>().expect("Invalid linger time") * 1000000.0,
+            "--seed" => seed = Some(args[i + 1].parse().expect("Invalid seed")),
+            "--trace-format" => trace_format = parse_trace_format(&args[i + 1]),
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
+    
+    let rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    
+    return SimOptions {
+        config: config,
+        rng: rng,
+        trace_format: trace_format,
+    };
+}
+
+// Run a client/relay machine pair over a directory of undefended traces, and report
+// the bandwidth and latency overheads.
+fn overhead_main(args: &[String]) {
+    assert!(args.len() >= 5, "Usage: {} overhead <client machines> <relay machines> <trace path> [--delay <ms>] [--jitter <ms>] [--linger <sec>] [--seed <seed>] [--trace-format wang|signed]\n{}", &args[0], SEED_NOTE);
+    
+    let client_machines = parse_machines(&args[2]);
+    let relay_machines = parse_machines(&args[3]);
+    let mut sim_options = parse_sim_options(&args[5..]);
+    
+    let traces: Vec<Vec<Cell>> = list_files(&[args[4].clone()]).iter()
+        .map(|trace_file| read_trace(trace_file, sim_options.trace_format))
+        .collect();
+    let overheads = measure(&client_machines, &relay_machines, &traces, sim_options.config, &mut sim_options.rng);
+    
+    print_summary(&summarize(&overheads));
+}
+
+fn print_summary(summary: &Summary) {
+    let mut header = format!("{:<10}{:>11}{:>9}{:>9}", "overhead", "aggregate", "mean", "median");
+    for (p, _) in summary.total.percentiles.iter() {
+        header += &format!("{:>9}", format!("p{}", p));
+    }
+    println!("{}", header);
+    
+    let rows: [(&str, &Stats); 4] = [
+        ("client", &summary.client),
+        ("relay", &summary.relay),
+        ("total", &summary.total),
+        ("latency", &summary.latency),
+    ];
+    for (name, stats) in rows.iter() {
+        let mut row = format!("{:<10}{:>10.1}%{:>8.1}%{:>8.1}%", name, stats.aggregate * 100.0, stats.mean * 100.0, stats.median * 100.0);
+        for (_, value) in stats.percentiles.iter() {
+            row += &format!("{:>8.1}%", value * 100.0);
+        }
+        println!("{}", row);
+    }
+    
+    println!("Traces: {}", summary.num_traces);
+    if summary.incomplete > 0 {
+        println!("Warning: {} traces had non-padding cells that were not delivered (blocked or past the linger time)", summary.incomplete);
+    }
+}
+
+// Generate machines for every combination of parameter values, measure their size and
+// state count, and their overhead if traces are given, and write a CSV or JSON table.
+fn sweep_main(args: &[String]) {
+    assert!(args.len() >= 3, "Usage: {} sweep <defense> <param>=<values>... [--traces <trace path>] [--output <file>] [--threads <threads>] [simulation options]\n  defenses: front (W, N, states), pipelined-front (W, N, pipelines, states), regulator (R, D, T, U, cells), tamaraw (rho_out, rho_in, L), buflo (rho, tau), cs-buflo (rho, levels, cells), constant-rate (rate, idle), delay (delay, prob)", &args[0]);
+    
+    let defense = find_defense(&args[2]);
+    let mut param_values: Vec<Option<Vec<f64>>> = vec![None; defense.params.len()];
+    let mut trace_path: Option<String> = None;
+    let mut output_file = String::from("sweep.csv");
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut sim_args: Vec<String> = Vec::new();
+    
+    let mut i = 3;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            let (name, spec) = args[i].split_once('=').expect("Parameters are given as <param>=<values>");
+            let index = defense.params.iter().position(|param| *param == name)
+                .unwrap_or_else(|| panic!("Unknown parameter for {}: {}", defense.name, name));
+            let values = parse_values(spec);
+            for value in values.iter() {
+                defense.check_value(index, *value);
+            }
+            param_values[index] = Some(values);
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--traces" => trace_path = Some(args[i + 1].clone()),
+            "--output" => output_file = args[i + 1].clone(),
+            "--threads" => threads = args[i + 1].parse().expect("Invalid number of threads"),
+            _ => sim_args.extend_from_slice(&args[i..i + 2]),
+        }
+        i += 2;
+    }
+    
+    let param_values: Vec<Vec<f64>> = param_values.into_iter().zip(defense.params.iter())
+        .map(|(values, param)| values.unwrap_or_else(|| panic!("Missing values for parameter {}", param)))
+        .collect();
+    let mut sim_options = parse_sim_options(&sim_args);
+    
+    // Every combination of values, in order with the last parameter varying fastest
+    let mut points: Vec<Vec<f64>> = vec![Vec::new()];
+    for values in param_values.iter() {
+        points = points.iter()
+            .flat_map(|point| values.iter().map(move |value| [point.as_slice(), &[*value]].concat()))
+            .collect();
+    }
+    
+    let traces: Option<Vec<Vec<Cell>>> = trace_path.map(|path| list_files(&[path]).iter()
+        .map(|trace_file| read_trace(trace_file, sim_options.trace_format))
+        .collect());
+    
+    // Each point gets its own seed, so the results do not depend on the number of threads
+    let seeds: Vec<u64> = points.iter().map(|_| sim_options.rng.gen()).collect();
+    
+    let next = AtomicUsize::new(0);
+    let rows: Mutex<Vec<Option<SweepRow>>> = Mutex::new(points.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= points.len() {
+                    break;
+                }
+                
+                let row = sweep_point(defense, &points[index], traces.as_deref(), sim_options.config, seeds[index]);
+                rows.lock().unwrap()[index] = Some(row);
+            });
+        }
+    });
+    
+    let rows: Vec<SweepRow> = rows.into_inner().unwrap().into_iter().map(|row| row.unwrap()).collect();
+    write_sweep(&output_file, defense, &rows);
+    println!("Wrote {} rows to {}", rows.len(), output_file);
+}
+
+fn sweep_point(defense: &Defense, values: &[f64], traces: Option<&[Vec<Cell>]>, config: SimConfig, seed: u64) -> SweepRow {
+    let generated = generate(defense, values);
+    let client_machine = generated.client_machine();
+    let relay_machine = generated.relay_machine();
+    
+    let summary = traces.map(|traces| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        summarize(&measure(&[client_machine.clone()], &[relay_machine.clone()], traces, config, &mut rng))
+    });
+    
+    return SweepRow {
+        values: values.to_vec(),
+        client_bytes: generated.client.len(),
+        client_states: client_machine.states.len(),
+        relay_bytes: generated.relay.len(),
+        relay_states: relay_machine.states.len(),
+        summary: summary,
+    };
+}
+
+// Write the rows of a sweep as JSON if the file name ends in .json, and as CSV otherwise.
+// Overheads are fractions (e.g., 0.5 is 50%).
+fn write_sweep(filename: &str, defense: &Defense, rows: &[SweepRow]) {
+    let file = File::create(filename).expect("Couldn't create output file");
+    let mut writer = BufWriter::new(file);
+    
+    let mut columns: Vec<String> = defense.params.iter().map(|param| param.to_string()).collect();
+    columns.extend(["client_bytes", "client_states", "relay_bytes", "relay_states"].iter().map(|column| column.to_string()));
+    if rows.iter().any(|row| row.summary.is_some()) {
+        for overhead in ["client", "relay", "total", "latency"].iter() {
+            for stat in ["aggregate", "mean", "median"].iter() {
+                columns.push(format!("{}_{}", overhead, stat));
+            }
+        }
+    }
+    
+    let json = filename.ends_with(".json");
+    if json {
+        writeln!(writer, "[").expect("Couldn't write output file");
+    } else {
+        writeln!(writer, "{}", columns.join(",")).expect("Couldn't write output file");
+    }
+    
+    // JSON has no NaN or infinity, so those are written as null
+    let number = |value: f64, text: String| -> String {
+        if json && !value.is_finite() {
+            return String::from("null");
+        }
+        return text;
+    };
+    
+    for (i, row) in rows.iter().enumerate() {
+        let mut fields: Vec<String> = row.values.iter().map(|value| number(*value, value.to_string())).collect();
+        fields.push(row.client_bytes.to_string());
+        fields.push(row.client_states.to_string());
+        fields.push(row.relay_bytes.to_string());
+        fields.push(row.relay_states.to_string());
+        if let Some(summary) = &row.summary {
+            for stats in [&summary.client, &summary.relay, &summary.total, &summary.latency].iter() {
+                fields.push(number(stats.aggregate, format!("{:.6}", stats.aggregate)));
+                fields.push(number(stats.mean, format!("{:.6}", stats.mean)));
+                fields.push(number(stats.median, format!("{:.6}", stats.median)));
+            }
+        }
+        
+        if !json {
+            writeln!(writer, "{}", fields.join(",")).expect("Couldn't write output file");
+            continue;
+        }
+        
+        let members: Vec<String> = columns.iter().zip(fields.iter())
+            .map(|(column, field)| format!("\"{}\": {}", column, field))
+            .collect();
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(writer, "  {{{}}}{}", members.join(", "), separator).expect("Couldn't write output file");
+    }
+    
+    if json {
+        writeln!(writer, "]").expect("Couldn't write output file");
+    }
+}
+
+// The chosen statistic of the chosen overhead of a summary
+fn pick_overhead(summary: &Summary, metric: &str, stat: &str) -> f64 {
+    let stats = match metric {
+        "client" => &summary.client,
+        "relay" => &summary.relay,
+        "total" => &summary.total,
+        "latency" => &summary.latency,
+        _ => panic!("Unknown overhead metric: {}", metric),
+    };
+    
+    match stat {
+        "aggregate" => stats.aggregate,
+        "mean" => stats.mean,
+        "median" => stats.median,
+        _ => panic!("Unknown overhead statistic: {}", stat),
+    }
+}
+
+// Binary-search one parameter of a defense, between a low and a high value, for the
+// value with the most overhead that stays within the target (see generate::tune), and
+// print it along with its machines.
+fn tune_main(args: &[String]) {
+    assert!(args.len() >= 4, "Usage: {} tune <defense> <param>=<low>:<high> <param>=<value>... --traces <trace path> --target <percent> [--metric client|relay|total|latency] [--stat aggregate|mean|median] [--resolution <step>] [simulation options]", &args[0]);
+    
+    let defense = find_defense(&args[2]);
+    let mut values: Vec<Option<f64>> = vec![None; defense.params.len()];
+    let mut tuned: Option<(usize, f64, f64)> = None; // (parameter index, low, high)
+    let mut trace_path: Option<String> = None;
+    let mut target: Option<f64> = None;
+    let mut metric = String::from("total");
+    let mut stat = String::from("aggregate");
+    let mut resolution = 1.0;
+    let mut sim_args: Vec<String> = Vec::new();
+    
+    let mut i = 3;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            let (name, spec) = args[i].split_once('=').expect("Parameters are given as <param>=<value> or <param>=<low>:<high>");
+            let index = defense.params.iter().position(|param| *param == name)
+                .unwrap_or_else(|| panic!("Unknown parameter for {}: {}", defense.name, name));
+            if let Some((low, high)) = spec.split_once(':') {
+                assert!(tuned.is_none(), "Only one parameter can be tuned");
+                let low: f64 = low.parse().expect("Invalid low value");
+                let high: f64 = high.parse().expect("Invalid high value");
+                assert!(low < high, "Low value must be below high value");
+                tuned = Some((index, low, high));
+            } else {
+                let value: f64 = spec.parse().expect("Invalid parameter value");
+                defense.check_value(index, value);
+                values[index] = Some(value);
+            }
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--traces" => trace_path = Some(args[i + 1].clone()),
+            "--target" => target = Some(args[i + 1].trim_end_matches('%').parse::<f64>().expect("Invalid target") / 100.0),
+            "--metric" => metric = args[i + 1].clone(),
+            "--stat" => stat = args[i + 1].clone(),
+            "--resolution" => resolution = args[i + 1].parse().expect("Invalid resolution"),
+            _ => sim_args.extend_from_slice(&args[i..i + 2]),
+        }
+        i += 2;
+    }
+    
+    let (index, low, high) = tuned.expect("No parameter to tune (give one as <param>=<low>:<high>)");
+    let target = target.expect("Missing --target");
+    let trace_path = trace_path.expect("Missing --traces");
+    assert!(resolution > 0.0, "Resolution must be positive");
+    // Searched values are the low value plus multiples of the resolution, so they are whole
+    // numbers for integer parameters only if both are
+    defense.check_value(index, low);
+    defense.check_value(index, high);
+    assert!(!defense.is_integer(index) || resolution.fract() == 0.0,
+        "Parameter {} of {} is a whole number, so the resolution must be one too", defense.params[index], defense.name);
+    values[index] = Some(low);
+    let mut values: Vec<f64> = values.into_iter().zip(defense.params.iter())
+        .map(|(value, param)| value.unwrap_or_else(|| panic!("Missing value for parameter {}", param)))
+        .collect();
+    
+    let mut sim_options = parse_sim_options(&sim_args);
+    let traces: Vec<Vec<Cell>> = list_files(&[trace_path]).iter()
+        .map(|trace_file| read_trace(trace_file, sim_options.trace_format))
+        .collect();
+    
+    // Every value is simulated with the same seed, so that values are compared on the same
+    // random draws
+    let seed: u64 = sim_options.rng.gen();
+    let param = defense.params[index];
+    let evaluate = |value: f64| -> f64 {
+        values[index] = value;
+        let row = sweep_point(defense, &values, Some(traces.as_slice()), sim_options.config, seed);
+        let overhead = pick_overhead(row.summary.as_ref().unwrap(), &metric, &stat);
+        println!("{} = {}: {} {} overhead {:.1}%", param, value, metric, stat, overhead * 100.0);
+        return overhead;
+    };
+    
+    let best = match tune(low, high, resolution, target, evaluate) {
+        Some(best) => best,
+        None => {
+            println!("No value of {} between {} and {} meets the target of {:.1}%", param, low, high, target * 100.0);
+            process::exit(1);
+        }
+    };
+    
+    values[index] = best.0;
+    let generated = generate(defense, &values);
+    println!();
+    println!("Best value: {} = {} ({} {} overhead {:.1}%, target {:.1}%)", param, best.0, metric, stat, best.1 * 100.0, target * 100.0);
+    println!();
+    println!("Client machine: {} ({})", generated.client, generated.client.len());
+    println!();
+    println!("Relay machine: {} ({})", generated.relay, generated.relay.len());
+    println!();
+}
+
+// Read labelled traces, and run them through the simulator first if machines are given.
+fn load_traces(trace_files: &[String], machines: &Option<(Vec<Machine>, Vec<Machine>)>, sim_options: &mut SimOptions) -> Vec<Vec<Cell>> {
+    return trace_files.iter().map(|trace_file| {
+        let trace = read_trace(trace_file, sim_options.trace_format);
+        match machines {
+            Some((client_machines, relay_machines)) => simulate(client_machines, relay_machines, &trace, sim_options.config, &mut sim_options.rng).trace(),
+            None => trace,
+        }
+    }).collect();
+}
+
+// Parse --client and --relay, which are given together (or not at all).
+fn parse_machine_options(client: Option<String>, relay: Option<String>) -> Option<(Vec<Machine>, Vec<Machine>)> {
+    match (client, relay) {
+        (Some(client), Some(relay)) => Some((parse_machines(&client), parse_machines(&relay))),
+        (None, None) => None,
+        _ => panic!("--client and --relay must be given together"),
+    }
+}
+
+// Run the kNN attack of Wang et al. over a labelled directory of traces (see
+// trace::site_label) with k-fold cross-validation, and report closed-world accuracy, or
+// open-world true and false positive rates and precision.
+fn evaluate_main(args: &[String]) {
+    assert!(args.len() >= 3, "Usage: {} evaluate <trace path> [--open-world] [--folds <folds>] [--k <neighbors>] [--learn <points>] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]", &args[0]);
+    
+    let trace_path = &args[2];
+    let mut open_world = false;
+    let mut folds = FOLDS;
+    let mut k = K;
+    let mut learn = LEARN;
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut client: Option<String> = None;
+    let mut relay: Option<String> = None;
+    let mut sim_args: Vec<String> = Vec::new();
+    
+    let mut i = 3;
+    while i < args.len() {
+        if args[i] == "--open-world" {
+            open_world = true;
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--folds" => folds = args[i + 1].parse().expect("Invalid number of folds"),
+            "--k" => k = args[i + 1].parse().expect("Invalid number of neighbors"),
+            "--learn" => learn = args[i + 1].parse().expect("Invalid number of points"),
+            "--threads" => threads = args[i + 1].parse().expect("Invalid number of threads"),
+            "--client" => client = Some(args[i + 1].clone()),
+            "--relay" => relay = Some(args[i + 1].clone()),
+            _ => sim_args.extend_from_slice(&args[i..i + 2]),
+        }
+        i += 2;
+    }
+    assert!(folds >= 2, "At least two folds are needed");
+    assert!(k >= 1, "At least one neighbor is needed");
+    
+    let machines = parse_machine_options(client, relay);
+    let mut sim_options = parse_sim_options(&sim_args);
+    
+    // The closed world only has monitored sites
+    let trace_files: Vec<String> = list_files(&[trace_path.clone()]).into_iter()
+        .filter(|trace_file| open_world || site_label(trace_file).is_some())
+        .collect();
+    let labels: Vec<Option<usize>> = trace_files.iter().map(|trace_file| site_label(trace_file)).collect();
+    let features: Vec<Vec<f64>> = load_traces(&trace_files, &machines, &mut sim_options).iter()
+        .map(|trace| knn_features(trace))
+        .collect();
+    assert!(features.len() >= folds, "Fewer traces than folds");
+    
+    let mut order: Vec<usize> = (0..features.len()).collect();
+    order.shuffle(&mut sim_options.rng);
+    
+    let mut accuracies: Vec<f64> = Vec::new();
+    let (mut true_positives, mut wrong_positives, mut false_negatives) = (0, 0, 0);
+    let (mut false_positives, mut true_negatives) = (0, 0);
+    
+    for fold in 0..folds {
+        let test: Vec<usize> = order.iter().enumerate().filter(|(j, _)| j % folds == fold).map(|(_, &index)| index).collect();
+        let train: Vec<usize> = order.iter().enumerate().filter(|(j, _)| j % folds != fold).map(|(_, &index)| index).collect();
+        
+        let train_features: Vec<Vec<f64>> = train.iter().map(|&index| features[index].clone()).collect();
+        let train_labels: Vec<Option<usize>> = train.iter().map(|&index| labels[index]).collect();
+        let test_features: Vec<Vec<f64>> = test.iter().map(|&index| features[index].clone()).collect();
+        
+        let weights = learn_weights(&train_features, &train_labels, learn, &mut sim_options.rng);
+        let predictions = classify_all(&train_features, &train_labels, &weights, &test_features, k, open_world, threads);
+        
+        let mut correct = 0;
+        for (&index, prediction) in test.iter().zip(predictions.iter()) {
+            match (labels[index], *prediction) {
+                (Some(site), Some(predicted)) if site == predicted => { correct += 1; true_positives += 1; }
+                (Some(_), Some(_)) => wrong_positives += 1,
+                (Some(_), None) => false_negatives += 1,
+                (None, Some(_)) => false_positives += 1,
+                (None, None) => { correct += 1; true_negatives += 1; }
+            }
+        }
+        accuracies.push(correct as f64 / test.len() as f64);
+        println!("Fold {}: accuracy {:.2}%", fold + 1, accuracies[fold] * 100.0);
+    }
+    
+    if !open_world {
+        let accuracy = accuracies.iter().sum::<f64>() / folds as f64;
+        let variance = accuracies.iter().map(|value| (value - accuracy).powi(2)).sum::<f64>() / folds as f64;
+        println!("Closed world ({} traces, {} folds, k = {}): accuracy {:.2}% ± {:.2}%", features.len(), folds, k, accuracy * 100.0, variance.sqrt() * 100.0);
+        return;
+    }
+    
+    // Predicting the wrong monitored site counts against precision, as in Wang et al.
+    let monitored = true_positives + wrong_positives + false_negatives;
+    let unmonitored = false_positives + true_negatives;
+    let ratio = |num: usize, denom: usize| if denom == 0 { 0.0 } else { num as f64 / denom as f64 };
+    println!("Open world ({} monitored, {} unmonitored traces, {} folds, k = {}):", monitored, unmonitored, folds, k);
+    println!("  TPR (recall): {:.2}%", ratio(true_positives, monitored) * 100.0);
+    println!("  FPR: {:.2}%", ratio(false_positives, unmonitored) * 100.0);
+    println!("  Precision: {:.2}%", ratio(true_positives, true_positives + wrong_positives + false_positives) * 100.0);
+    println!("  Wrong monitored site: {:.2}%", ratio(wrong_positives, monitored) * 100.0);
+}
+
+// Write features of a labelled directory of traces (see trace::site_label) to a CSV file,
+// one row per trace: the site (-1 if unmonitored), the file name, then the features of
+// each set in order. Traces are run through the simulator first if machines are given.
+fn features_main(args: &[String]) {
+    assert!(args.len() >= 4, "Usage: {} features <trace path> <output file> [--set knn|cumul|kfp|bursts,...] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]", &args[0]);
+    
+    let trace_path = &args[2];
+    let output_file = &args[3];
+    let mut sets: Vec<(String, FeatureSet)> = vec![(String::from("cumul"), FeatureSet::Cumul)];
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut client: Option<String> = None;
+    let mut relay: Option<String> = None;
+    let mut sim_args: Vec<String> = Vec::new();
+    
+    let mut i = 4;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--set" => sets = args[i + 1].split(',').map(|name| (name.to_string(), parse_feature_set(name))).collect(),
+            "--threads" => threads = args[i + 1].parse().expect("Invalid number of threads"),
+            "--client" => client = Some(args[i + 1].clone()),
+            "--relay" => relay = Some(args[i + 1].clone()),
+            _ => sim_args.extend_from_slice(&args[i..i + 2]),
+        }
+        i += 2;
+    }
+    
+    let machines = parse_machine_options(client, relay);
+    let mut sim_options = parse_sim_options(&sim_args);
+    let trace_files = list_files(&[trace_path.clone()]);
+    
+    // Every set has a fixed number of features
+    let mut names: Vec<String> = Vec::new();
+    for (name, set) in sets.iter() {
+        names.extend((0..extract_features(*set, &[]).len()).map(|j| format!("{}_{}", name, j)));
+    }
+    
+    let file = File::create(output_file).expect("Couldn't create output file");
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "site,file,{}", names.join(",")).expect("Couldn't write output file");
+    
+    // Traces are processed in batches, in parallel, and written in order; each trace gets
+    // its own seed, so the output does not depend on the number of threads
+    let threads = threads.max(1);
+    for batch in trace_files.chunks(threads * BATCH) {
+        let seeds: Vec<u64> = batch.iter().map(|_| sim_options.rng.gen()).collect();
+        let jobs: Vec<(&String, u64)> = batch.iter().zip(seeds.into_iter()).collect();
+        let chunk_size = (jobs.len() + threads - 1) / threads;
+        
+        let rows: Vec<Vec<f64>> = thread::scope(|scope| {
+            let handles: Vec<_> = jobs.chunks(chunk_size).map(|chunk| {
+                let machines = &machines;
+                let sets = &sets;
+                let config = sim_options.config;
+                let trace_format = sim_options.trace_format;
+                scope.spawn(move || chunk.iter().map(|&(trace_file, seed)| {
+                    let mut trace = read_trace(trace_file, trace_format);
+                    if let Some((client_machines, relay_machines)) = machines {
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        trace = simulate(client_machines, relay_machines, &trace, config, &mut rng).trace();
+                    }
+                    
+                    sets.iter().flat_map(|(_, set)| extract_features(*set, &trace)).collect::<Vec<f64>>()
+                }).collect::<Vec<Vec<f64>>>())
+            }).collect();
+            
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        
+        for (trace_file, features) in batch.iter().zip(rows.iter()) {
+            let site = site_label(trace_file).map(|site| site as i64).unwrap_or(-1);
+            let name = Path::new(trace_file).file_name().unwrap().to_string_lossy().into_owned();
+            let values: Vec<String> = features.iter().map(|value| value.to_string()).collect();
+            writeln!(writer, "{},{},{}", site, name, values.join(",")).expect("Couldn't write output file");
+        }
+    }
+    
+    println!("Wrote features of {} traces to {}", trace_files.len(), output_file);
+}
+
+// Estimate the leakage of each feature about the site over the monitored traces of a
+// labelled directory (see trace::site_label and leakage::feature_leakage). If machines are
+// given, the traces are taken as undefended, and the leakage is estimated both before and
+// after running them through the simulator.
+fn leakage_main(args: &[String]) {
+    assert!(args.len() >= 3, "Usage: {} leakage <trace path> [--set knn|cumul|kfp|bursts,...] [--grid <points>] [--top <features>] [--output <file>] [--threads <threads>] [--client <machines> --relay <machines>] [simulation options]", &args[0]);
+    
+    let trace_path = &args[2];
+    let mut sets: Vec<(String, FeatureSet)> = vec![(String::from("kfp"), FeatureSet::Kfp)];
+    let mut grid = GRID;
+    let mut top = TOP;
+    let mut output_file: Option<String> = None;
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut client: Option<String> = None;
+    let mut relay: Option<String> = None;
+    let mut sim_args: Vec<String> = Vec::new();
+    
+    let mut i = 3;
+    while i < args.len() {
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--set" => sets = args[i + 1].split(',').map(|name| (name.to_string(), parse_feature_set(name))).collect(),
+            "--grid" => grid = args[i + 1].parse().expect("Invalid number of grid points"),
+            "--top" => top = args[i + 1].parse().expect("Invalid number of features"),
+            "--output" => output_file = Some(args[i + 1].clone()),
+            "--threads" => threads = args[i + 1].parse().expect("Invalid number of threads"),
+            "--client" => client = Some(args[i + 1].clone()),
+            "--relay" => relay = Some(args[i + 1].clone()),
+            _ => sim_args.extend_from_slice(&args[i..i + 2]),
+        }
+        i += 2;
+    }
+    
+    let machines = parse_machine_options(client, relay);
+    let mut sim_options = parse_sim_options(&sim_args);
+    
+    // Classes are the monitored sites, numbered in order
+    let trace_files: Vec<String> = list_files(&[trace_path.clone()]).into_iter()
+        .filter(|trace_file| site_label(trace_file).is_some())
+        .collect();
+    let mut sites: Vec<usize> = trace_files.iter().map(|trace_file| site_label(trace_file).unwrap()).collect();
+    sites.sort();
+    sites.dedup();
+    let classes: Vec<usize> = trace_files.iter()
+        .map(|trace_file| sites.binary_search(&site_label(trace_file).unwrap()).unwrap())
+        .collect();
+    
+    let mut names: Vec<String> = Vec::new();
+    for (name, set) in sets.iter() {
+        names.extend((0..extract_features(*set, &[]).len()).map(|j| format!("{}_{}", name, j)));
+    }
+    let extract = |traces: &[Vec<Cell>]| -> Vec<Vec<f64>> {
+        traces.iter().map(|trace| sets.iter().flat_map(|(_, set)| extract_features(*set, trace)).collect()).collect()
+    };
+    
+    // (label, leakage of each feature) for the traces as given, then the defended ones
+    let mut results: Vec<(&str, Vec<f64>)> = Vec::new();
+    let undefended = extract(&load_traces(&trace_files, &None, &mut sim_options)[..]);
+    let label = if machines.is_some() { "undefended" } else { "leakage" };
+    results.push((label, leakage_all(&undefended, &classes, sites.len(), grid, threads)));
+    if machines.is_some() {
+        let defended = extract(&load_traces(&trace_files, &machines, &mut sim_options)[..]);
+        results.push(("defended", leakage_all(&defended, &classes, sites.len(), grid, threads)));
+    }
+    
+    println!("Leakage (bits) over {} traces of {} sites, at most {:.2} bits:", trace_files.len(), sites.len(), (sites.len() as f64).log2());
+    for (label, leakage) in results.iter() {
+        let mean = leakage.iter().sum::<f64>() / leakage.len().max(1) as f64;
+        let max = leakage.iter().cloned().fold(0.0, f64::max);
+        println!("  {}: mean {:.3}, max {:.3}", label, mean, max);
+    }
+    
+    // Features ordered by their leakage over the traces as given, with NaN (if any) last
+    let leakage = |i: usize| if results[0].1[i].is_nan() { f64::NEG_INFINITY } else { results[0].1[i] };
+    let mut order: Vec<usize> = (0..names.len()).collect();
+    order.sort_by(|&a, &b| leakage(b).total_cmp(&leakage(a)));
+    
+    let mut header = format!("{:<16}", "feature");
+    for (label, _) in results.iter() {
+        header += &format!("{:>12}", label);
+    }
+    println!("{}", header);
+    for &f in order.iter().take(top) {
+        let mut row = format!("{:<16}", names[f]);
+        for (_, leakage) in results.iter() {
+            row += &format!("{:>12.3}", leakage[f]);
+        }
+        println!("{}", row);
+    }
+    
+    if let Some(output_file) = output_file {
+        let file = File::create(&output_file).expect("Couldn't create output file");
+        let mut writer = BufWriter::new(file);
+        let labels: Vec<&str> = results.iter().map(|(label, _)| *label).collect();
+        writeln!(writer, "feature,{}", labels.join(",")).expect("Couldn't write output file");
+        for f in 0..names.len() {
+            let values: Vec<String> = results.iter().map(|(_, leakage)| format!("{:.6}", leakage[f])).collect();
+            writeln!(writer, "{},{}", names[f], values.join(",")).expect("Couldn't write output file");
+        }
+        println!("Wrote leakage of {} features to {}", names.len(), output_file);
+    }
+}
@@ -0,0 +1,162 @@
+// Maybenot Walkie-Talkie -- molds traffic to a common supersequence of bursts to approximate the Walkie-Talkie defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::mem;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use maybenot_defenses::trace::{list_files, parse_trace_format};
+use maybenot_defenses::surakav::{default_options, parse_pacing, read_lines, read_cell_trace, write_reference, exit_on_error, merge_small_bursts, build_machines, Burst};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() >= 2, "Usage: {} <trace path>... [--output <ref file>] [--cutoff <bursts>] [--pacing <spec>] [--seed <seed>] [--trace-format wang|signed] [--burst-gap <ms>]", &args[0]);
+    
+    let mut options = default_options();
+    let mut trace_paths: Vec<String> = Vec::new();
+    let mut output_file: Option<String> = None;
+    let mut rng = StdRng::from_entropy(); // randomness for building the machines
+    
+    let mut i = 1;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            trace_paths.push(args[i].clone());
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--output" => output_file = Some(args[i + 1].clone()),
+            "--cutoff" => options.cutoff_length = args[i + 1].parse().expect("Invalid cutoff"),
+            "--pacing" => options.send_timeout = parse_pacing(&args[i + 1]),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--trace-format" => options.trace_format = Some(parse_trace_format(&args[i + 1])),
+            "--burst-gap" => options.burst_gap = args[i + 1].parse::<f64>().expect("Invalid burst gap") * 1000.0,
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
+    
+    let trace_files = list_files(&trace_paths);
+    assert!(trace_files.len() >= 2, "Walkie-Talkie needs at least two traces");
+    
+    // Timing is dropped, as the supersequence is sent with the send pacing
+    let mut supersequence: Vec<Burst> = Vec::new();
+    let mut trace_cells: Vec<usize> = Vec::with_capacity(trace_files.len());
+    for trace_file in trace_files.iter() {
+        let (mut bursts, _) = exit_on_error(match options.trace_format {
+            Some(format) => read_cell_trace(trace_file, format, options.burst_gap),
+            None => read_lines(trace_file, options.cutoff_length),
+        });
+        bursts.truncate(options.cutoff_length);
+        
+        let num_cells: usize = bursts.iter().map(|burst| burst.num_cells).sum();
+        println!("Trace {}: {} bursts, {} cells", trace_file, bursts.len(), num_cells);
+        trace_cells.push(num_cells);
+        
+        let untimed: Vec<Burst> = bursts.iter().map(|burst| Burst {
+            relay_sending: burst.relay_sending,
+            num_cells: burst.num_cells,
+            start: 0.0,
+            duration: 0.0,
+        }).collect();
+        supersequence = shortest_supersequence(&supersequence, &untimed);
+    }
+    
+    let total_cells: usize = supersequence.iter().map(|burst| burst.num_cells).sum();
+    let mean_overhead = trace_cells.iter().map(|&cells| (total_cells as f64 - cells as f64) / cells.max(1) as f64).sum::<f64>() / trace_cells.len() as f64;
+    println!("Supersequence: {} bursts, {} cells, {:.1}% more cells than the traces on average", supersequence.len(), total_cells, 100.0 * mean_overhead);
+    
+    if let Some(output_file) = output_file {
+        exit_on_error(write_reference(&output_file, &supersequence, false));
+        println!("Wrote the supersequence to {}", output_file);
+    }
+    
+    options.weights = vec![1.0];
+    let references = vec![(String::from("supersequence"), supersequence, false)];
+    let max_bursts = references[0].1.len();
+    let (client_machine, relay_machine) = build_machines(&references, max_bursts, &options, &mut rng, None);
+    let client_machine = client_machine.serialize();
+    let relay_machine = relay_machine.serialize();
+    println!("Client machine: {} ({})\n", client_machine, client_machine.len());
+    println!("Relay machine: {} ({})\n", relay_machine, relay_machine.len());
+}
+
+// Shortest common supersequence of two burst sequences, by the number of cells (then
+// bursts): each burst of either sequence is covered by a burst of the supersequence in
+// the same direction and at least as large, in order. A burst of the supersequence can
+// cover one burst of each sequence, at the size of the larger one. Adjacent bursts in the
+// same direction are merged. Folding more sequences in one at a time gives a common
+// supersequence of all of them, but not necessarily the shortest.
+fn shortest_supersequence(a: &[Burst], b: &[Burst]) -> Vec<Burst> {
+    // cost[j] is the (cells, bursts) of the shortest supersequence of a[..i] and b[..j],
+    // for the current row i, and choice records the last burst taken:
+    // 0 --> a[i - 1], 1 --> b[j - 1], 2 --> both
+    let width = b.len() + 1;
+    let mut choice: Vec<u8> = vec![0; (a.len() + 1) * width];
+    let mut prev_cost: Vec<(usize, usize)> = vec![(0, 0); width];
+    let mut cost: Vec<(usize, usize)> = vec![(0, 0); width];
+    
+    for j in 1..width {
+        cost[j] = (cost[j - 1].0 + b[j - 1].num_cells, j);
+        choice[j] = 1;
+    }
+    
+    for i in 1..(a.len() + 1) {
+        mem::swap(&mut prev_cost, &mut cost);
+        cost[0] = (prev_cost[0].0 + a[i - 1].num_cells, i);
+        choice[i * width] = 0;
+        
+        for j in 1..width {
+            let mut best = (prev_cost[j].0 + a[i - 1].num_cells, prev_cost[j].1 + 1);
+            let mut best_choice = 0;
+            
+            let take_b = (cost[j - 1].0 + b[j - 1].num_cells, cost[j - 1].1 + 1);
+            if take_b < best {
+                best = take_b;
+                best_choice = 1;
+            }
+            
+            if a[i - 1].relay_sending == b[j - 1].relay_sending {
+                let take_both = (prev_cost[j - 1].0 + a[i - 1].num_cells.max(b[j - 1].num_cells), prev_cost[j - 1].1 + 1);
+                if take_both < best {
+                    best = take_both;
+                    best_choice = 2;
+                }
+            }
+            
+            cost[j] = best;
+            choice[i * width + j] = best_choice;
+        }
+    }
+    
+    // Trace back from the end of both sequences
+    let mut reversed: Vec<Burst> = Vec::with_capacity(a.len() + b.len());
+    let mut i = a.len();
+    let mut j = b.len();
+    while i > 0 || j > 0 {
+        match choice[i * width + j] {
+            0 => {
+                reversed.push(a[i - 1].clone());
+                i -= 1;
+            }
+            1 => {
+                reversed.push(b[j - 1].clone());
+                j -= 1;
+            }
+            _ => {
+                let mut burst = a[i - 1].clone();
+                burst.num_cells = burst.num_cells.max(b[j - 1].num_cells);
+                reversed.push(burst);
+                i -= 1;
+                j -= 1;
+            }
+        }
+    }
+    reversed.reverse();
+    
+    return merge_small_bursts(&reversed, 0).0;
+}
@@ -0,0 +1,252 @@
+// Maybenot WTF-PAD -- uses burst and gap modes, with timeouts fitted to undefended traces, to approximate the WTF-PAD defense
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::f64::consts::PI;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use maybenot_defenses::trace::{list_files, read_trace, parse_trace_format, Cell, TraceFormat};
+use maybenot_defenses::stats::{sample_standard_normal, mean_stdev};
+use maybenot_defenses::machines::constant_dist;
+use maybenot_defenses::wtfpad;
+
+use maybenot::dist::{Dist, DistType};
+
+const NUM_BINS: usize    = 40;     // power-of-two bins of the histograms (microseconds or cells)
+const FIT_SAMPLES: usize = 100000; // samples drawn from each candidate distribution to compare it with a histogram
+const EULER: f64         = 0.5772156649015329;
+
+// Samples from the traces for one side: time between cells sent in different bursts
+// (burst mode) and in the same burst (gap mode), both in microseconds, and cells per burst
+struct Samples {
+    burst: Vec<f64>,
+    gap: Vec<f64>,
+    lengths: Vec<f64>,
+}
+
+// A distribution fitted to a histogram, and its histogram from FIT_SAMPLES samples
+struct Fit {
+    name: &'static str,
+    dist: Dist,
+    histogram: Vec<f64>,
+    distance: f64, // total variation distance between the histograms
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    assert!(args.len() >= 2, "Usage: {} <trace path>... [--dist auto|lognormal|weibull|gamma] [--seed <seed>] [--histograms <file>] [--trace-format wang|signed]", &args[0]);
+    
+    let mut trace_paths: Vec<String> = Vec::new();
+    let mut dist_name = String::from("auto");
+    let mut rng = StdRng::from_entropy(); // randomness for comparing fitted distributions
+    let mut histogram_file: Option<String> = None;
+    let mut trace_format = TraceFormat::Wang;
+    
+    let mut i = 1;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            trace_paths.push(args[i].clone());
+            i += 1;
+            continue;
+        }
+        
+        assert!(i + 1 < args.len(), "Missing value for option {}", &args[i]);
+        match args[i].as_str() {
+            "--dist" => dist_name = args[i + 1].clone(),
+            "--seed" => rng = StdRng::seed_from_u64(args[i + 1].parse().expect("Invalid seed")),
+            "--histograms" => histogram_file = Some(args[i + 1].clone()),
+            "--trace-format" => trace_format = parse_trace_format(&args[i + 1]),
+            _ => panic!("Unknown option: {}", &args[i]),
+        }
+        i += 2;
+    }
+    
+    assert!(["auto", "lognormal", "weibull", "gamma"].contains(&dist_name.as_str()), "Unknown distribution: {}", dist_name);
+    assert!(trace_format == TraceFormat::Wang, "WTF-PAD histograms need timed traces");
+    
+    let trace_files = list_files(&trace_paths);
+    assert!(!trace_files.is_empty(), "No traces given");
+    let traces: Vec<Vec<Cell>> = trace_files.iter().map(|trace_file| read_trace(trace_file, trace_format)).collect();
+    println!("Loaded {} traces", traces.len());
+    
+    let mut histogram_rows: Vec<String> = Vec::new();
+    let mut machines: Vec<String> = Vec::new();
+    for (side, relay) in [("Client", false), ("Relay", true)] {
+        let samples = collect_samples(&traces, relay);
+        
+        let mut fits: Vec<Fit> = Vec::new();
+        for (mode, values) in [("burst", &samples.burst), ("gap", &samples.gap), ("length", &samples.lengths)] {
+            assert!(values.len() >= 2, "Not enough {} mode samples for the {} side", mode, side.to_lowercase());
+            let fit = fit_histogram(values, &dist_name, &mut rng);
+            println!("{} {} mode: {} samples, {}({:.3}, {:.3}) up to {:.0} (distance {:.3})", side, mode, values.len(),
+                fit.name, fit.dist.param1, fit.dist.param2, fit.dist.max, fit.distance);
+            
+            let observed = histogram(values);
+            for b in 0..NUM_BINS {
+                if observed[b] > 0.0 || fit.histogram[b] > 0.0 {
+                    histogram_rows.push(format!("{},{},{},{},{},{:.6},{:.6}", side.to_lowercase(), mode, b, 1u64 << b, 1u64 << (b + 1), observed[b], fit.histogram[b]));
+                }
+            }
+            fits.push(fit);
+        }
+        
+        // A burst is followed by another burst, rather than being the last of the trace
+        let continue_prob = samples.burst.len() as f64 / samples.lengths.len() as f64;
+        let machine = wtfpad::generate_machine(&fits[0].dist, &fits[1].dist, &fits[2].dist, continue_prob);
+        machines.push(machine);
+    }
+    
+    if let Some(histogram_file) = histogram_file {
+        let file = File::create(&histogram_file).expect("Couldn't create histogram file");
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "side,mode,bin,low,high,observed,fitted").expect("Couldn't write histogram file");
+        for row in histogram_rows.iter() {
+            writeln!(writer, "{}", row).expect("Couldn't write histogram file");
+        }
+        println!("Wrote histograms to {}", histogram_file);
+    }
+    
+    println!("Client machine: {} ({})\n", machines[0], machines[0].len());
+    println!("Relay machine: {} ({})\n", machines[1], machines[1].len());
+}
+
+// Collect the samples for one side from the cells it sends. A burst is a run of cells sent
+// by the side without a cell in the other direction in between.
+fn collect_samples(traces: &[Vec<Cell>], relay: bool) -> Samples {
+    let mut samples = Samples {
+        burst: Vec::new(),
+        gap: Vec::new(),
+        lengths: Vec::new(),
+    };
+    
+    for cells in traces.iter() {
+        let mut last_sent: Option<f64> = None;
+        let mut interrupted = false;
+        let mut length = 0;
+        
+        for cell in cells.iter() {
+            if cell.relay_sending != relay {
+                interrupted = last_sent.is_some();
+                continue;
+            }
+            
+            // Cells at the same time are 1 microsecond apart, so logarithms are defined
+            if let Some(last_sent) = last_sent {
+                let interval = (cell.time - last_sent).max(1.0);
+                if interrupted {
+                    samples.burst.push(interval);
+                    samples.lengths.push(length as f64);
+                    length = 0;
+                } else {
+                    samples.gap.push(interval);
+                }
+            }
+            
+            length += 1;
+            last_sent = Some(cell.time);
+            interrupted = false;
+        }
+        
+        if length > 0 {
+            samples.lengths.push(length as f64);
+        }
+    }
+    
+    return samples;
+}
+
+// Fit a distribution to values: log-normal, Weibull and gamma distributions are fitted
+// from their moments (of the logarithms for the first two), capped at the largest value,
+// and the one whose histogram is closest to that of the values is kept (or the one named
+// by dist_name, unless it is "auto"). Values that are all the same give a constant.
+fn fit_histogram(values: &[f64], dist_name: &str, rng: &mut StdRng) -> Fit {
+    let observed = histogram(values);
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    
+    let (mean, stdev) = mean_stdev(values);
+    let logs: Vec<f64> = values.iter().map(|v| v.ln()).collect();
+    let (log_mean, log_stdev) = mean_stdev(&logs);
+    if stdev == 0.0 || log_stdev == 0.0 {
+        return Fit { name: "constant", dist: constant_dist(mean), histogram: histogram(&[mean]), distance: 0.0 };
+    }
+    
+    let weibull_shape = PI / (log_stdev * 6f64.sqrt());
+    let candidates = [
+        ("lognormal", DistType::LogNormal, log_mean, log_stdev),
+        ("weibull", DistType::Weibull, (log_mean + EULER / weibull_shape).exp(), weibull_shape),
+        ("gamma", DistType::Gamma, mean * mean / (stdev * stdev), stdev * stdev / mean),
+    ];
+    
+    let mut best: Option<Fit> = None;
+    for (name, dist_type, param1, param2) in candidates {
+        if dist_name != "auto" && dist_name != name {
+            continue;
+        }
+        
+        let sampled: Vec<f64> = (0..FIT_SAMPLES).map(|_| {
+            let sample = match dist_type {
+                DistType::LogNormal => (param1 + param2 * sample_standard_normal(rng)).exp(),
+                DistType::Weibull => param1 * (-(1.0 - rng.gen::<f64>()).ln()).powf(1.0 / param2),
+                _ => sample_gamma(param1, rng) * param2,
+            };
+            sample.min(max)
+        }).collect();
+        let fitted = histogram(&sampled);
+        let distance = 0.5 * observed.iter().zip(fitted.iter()).map(|(o, f)| (o - f).abs()).sum::<f64>();
+        
+        if best.as_ref().map_or(true, |best| distance < best.distance) {
+            let dist = Dist {
+                dist: dist_type,
+                param1: param1,
+                param2: param2,
+                start: 0.0,
+                max: max,
+            };
+            best = Some(Fit { name: name, dist: dist, histogram: fitted, distance: distance });
+        }
+    }
+    
+    return best.unwrap();
+}
+
+// Fraction of values in each power-of-two bin, [2^b, 2^(b+1)), with values below 1 in
+// the first bin and the last bin open-ended
+fn histogram(values: &[f64]) -> Vec<f64> {
+    let mut counts: Vec<f64> = vec![0.0; NUM_BINS];
+    for value in values.iter() {
+        let bin = value.max(1.0).log2().floor() as usize;
+        counts[bin.min(NUM_BINS - 1)] += 1.0;
+    }
+    
+    for count in counts.iter_mut() {
+        *count /= values.len() as f64;
+    }
+    return counts;
+}
+
+// Sample from the gamma distribution with unit scale (Marsaglia and Tsang's method)
+fn sample_gamma(shape: f64, rng: &mut StdRng) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = 1.0 - rng.gen::<f64>(); // (0, 1]
+        return sample_gamma(shape + 1.0, rng) * u.powf(1.0 / shape);
+    }
+    
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_standard_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
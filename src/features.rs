@@ -0,0 +1,407 @@
+// Features -- website fingerprinting features of cell-level traces
+
+use crate::trace::Cell;
+
+pub const MISSING: f64 = -1.0; // value of a feature a trace is too short to have
+
+const ORDERING_CELLS: usize        = 300; // cells in each direction with an ordering feature
+const CONCENTRATION_CHUNK: usize   = 30;  // cells per concentration chunk
+const CONCENTRATION_CHUNKS: usize  = 100;
+const FIRST_CELLS: usize           = 20;
+const CUMUL_POINTS: usize          = 100; // interpolated points of the CUMUL representation
+const KFP_CHUNK: usize             = 20;  // cells per k-FP concentration chunk
+const KFP_ALT_CONCENTRATION: usize = 70;  // sums of k-FP concentration chunks
+const KFP_ALT_PER_SECOND: usize    = 20;  // sums of k-FP cells per second
+const KFP_EDGE_CELLS: usize        = 30;  // first and last cells counted by k-FP
+
+// Feature sets, for export
+#[derive(Clone, Copy, PartialEq)]
+pub enum FeatureSet {
+    Knn,    // Wang et al. kNN (see knn_features)
+    Cumul,  // Panchenko et al. CUMUL (see cumul_features)
+    Kfp,    // Hayes and Danezis k-FP (see kfp_features)
+    Bursts, // burst statistics (see burst_features)
+}
+
+pub fn parse_feature_set(name: &str) -> FeatureSet {
+    match name {
+        "knn" => FeatureSet::Knn,
+        "cumul" => FeatureSet::Cumul,
+        "kfp" => FeatureSet::Kfp,
+        "bursts" => FeatureSet::Bursts,
+        _ => panic!("Unknown feature set: {}", name),
+    }
+}
+
+pub fn extract_features(set: FeatureSet, trace: &[Cell]) -> Vec<f64> {
+    match set {
+        FeatureSet::Knn => knn_features(trace),
+        FeatureSet::Cumul => cumul_features(trace),
+        FeatureSet::Kfp => kfp_features(trace),
+        FeatureSet::Bursts => burst_features(trace),
+    }
+}
+
+// Features of the Wang et al. kNN attack ("Effective Attacks and Provable Defenses for
+// Website Fingerprinting"), adapted to cells, whose size is constant:
+//  - number of cells, outgoing cells and incoming cells, and the load time (sec)
+//  - number of outgoing bursts, and their maximum and mean size, and the number of
+//    bursts larger than 5, 10 and 15 cells
+//  - direction of the first 20 cells (1 outgoing, 0 incoming)
+//  - position of each of the first 300 outgoing and 300 incoming cells
+//  - number of outgoing cells in each of the first 100 chunks of 30 cells
+// Features past the end of a short trace are MISSING.
+pub fn knn_features(trace: &[Cell]) -> Vec<f64> {
+    let mut features: Vec<f64> = Vec::new();
+    
+    let outgoing = trace.iter().filter(|cell| !cell.relay_sending).count();
+    features.push(trace.len() as f64);
+    features.push(outgoing as f64);
+    features.push((trace.len() - outgoing) as f64);
+    features.push(trace.last().map(|cell| cell.time / 1000000.0).unwrap_or(0.0));
+    
+    // Outgoing bursts, separated by incoming cells
+    let bursts = outgoing_bursts(trace);
+    features.push(bursts.len() as f64);
+    features.push(bursts.iter().max().copied().unwrap_or(0) as f64);
+    features.push(mean(&bursts.iter().map(|&burst| burst as f64).collect::<Vec<f64>>()));
+    for threshold in [5, 10, 15].iter() {
+        features.push(bursts.iter().filter(|&burst| burst > threshold).count() as f64);
+    }
+    
+    for i in 0..FIRST_CELLS {
+        features.push(match trace.get(i) {
+            Some(cell) => if cell.relay_sending { 0.0 } else { 1.0 },
+            None => MISSING,
+        });
+    }
+    
+    for relay_sending in [false, true].iter() {
+        let mut positions: Vec<f64> = trace.iter().enumerate()
+            .filter(|(_, cell)| cell.relay_sending == *relay_sending)
+            .map(|(i, _)| i as f64)
+            .take(ORDERING_CELLS)
+            .collect();
+        positions.resize(ORDERING_CELLS, MISSING);
+        features.append(&mut positions);
+    }
+    
+    let mut concentrations: Vec<f64> = trace.chunks(CONCENTRATION_CHUNK)
+        .map(|chunk| chunk.iter().filter(|cell| !cell.relay_sending).count() as f64)
+        .take(CONCENTRATION_CHUNKS)
+        .collect();
+    concentrations.resize(CONCENTRATION_CHUNKS, MISSING);
+    features.append(&mut concentrations);
+    
+    return features;
+}
+
+// Features of CUMUL (Panchenko et al., "Website Fingerprinting at Internet Scale"), for
+// cells: the number of incoming and outgoing cells, then the cumulative sum of cell
+// directions (outgoing 1, incoming -1) interpolated at 100 evenly spaced cells.
+pub fn cumul_features(trace: &[Cell]) -> Vec<f64> {
+    let outgoing = trace.iter().filter(|cell| !cell.relay_sending).count();
+    let mut features: Vec<f64> = vec![(trace.len() - outgoing) as f64, outgoing as f64];
+    
+    let mut cumulative: Vec<f64> = Vec::new();
+    let mut sum = 0.0;
+    for cell in trace.iter() {
+        sum += if cell.relay_sending { -1.0 } else { 1.0 };
+        cumulative.push(sum);
+    }
+    
+    for j in 0..CUMUL_POINTS {
+        if cumulative.is_empty() {
+            features.push(0.0);
+            continue;
+        }
+        
+        let position = j as f64 * (cumulative.len() - 1) as f64 / (CUMUL_POINTS - 1) as f64;
+        let lower = position.floor() as usize;
+        let upper = position.ceil() as usize;
+        features.push(cumulative[lower] + (cumulative[upper] - cumulative[lower]) * (position - lower as f64));
+    }
+    
+    return features;
+}
+
+// Features of k-FP (Hayes and Danezis, "k-fingerprinting: a Robust Scalable Website
+// Fingerprinting Technique"), for cells, in this order:
+//  - number of cells, incoming and outgoing, and the fraction incoming and outgoing (5)
+//  - mean and standard deviation of the positions of outgoing and incoming cells (4)
+//  - standard deviation, mean, median, maximum and minimum of the outgoing cells in
+//    each chunk of 20 cells (5)
+//  - maximum, mean, standard deviation and 75th percentile of the inter-arrival times of
+//    all, incoming and outgoing cells (12)
+//  - 25th, 50th, 75th and 100th percentiles of the times of all, incoming and outgoing
+//    cells (12)
+//  - mean, standard deviation, minimum, maximum and median of cells per second (5)
+//  - incoming and outgoing cells among the first and last 30 cells (4)
+//  - number of outgoing bursts, and their maximum and mean size, and the number of
+//    bursts larger than 5, 10 and 20 cells (6)
+//  - the chunk concentrations summed into 70 groups, and cells per second summed into 20
+//    groups (90)
+// Times are in seconds.
+pub fn kfp_features(trace: &[Cell]) -> Vec<f64> {
+    let mut features: Vec<f64> = Vec::new();
+    
+    let incoming: Vec<&Cell> = trace.iter().filter(|cell| cell.relay_sending).collect();
+    let outgoing: Vec<&Cell> = trace.iter().filter(|cell| !cell.relay_sending).collect();
+    let total = trace.len() as f64;
+    features.push(total);
+    features.push(incoming.len() as f64);
+    features.push(outgoing.len() as f64);
+    features.push(if total > 0.0 { incoming.len() as f64 / total } else { 0.0 });
+    features.push(if total > 0.0 { outgoing.len() as f64 / total } else { 0.0 });
+    
+    for relay_sending in [false, true].iter() {
+        let positions: Vec<f64> = trace.iter().enumerate()
+            .filter(|(_, cell)| cell.relay_sending == *relay_sending)
+            .map(|(i, _)| i as f64)
+            .collect();
+        features.push(mean(&positions));
+        features.push(stdev(&positions));
+    }
+    
+    let concentrations: Vec<f64> = trace.chunks(KFP_CHUNK)
+        .map(|chunk| chunk.iter().filter(|cell| !cell.relay_sending).count() as f64)
+        .collect();
+    features.push(stdev(&concentrations));
+    features.push(mean(&concentrations));
+    features.push(percentile(&concentrations, 50.0));
+    features.push(concentrations.iter().cloned().fold(0.0, f64::max));
+    features.push(if concentrations.is_empty() { 0.0 } else { concentrations.iter().cloned().fold(f64::INFINITY, f64::min) });
+    
+    let times = |cells: &[&Cell]| cells.iter().map(|cell| cell.time / 1000000.0).collect::<Vec<f64>>();
+    let all_cells: Vec<&Cell> = trace.iter().collect();
+    let time_sets = [times(&all_cells[..]), times(&incoming[..]), times(&outgoing[..])];
+    
+    for cell_times in time_sets.iter() {
+        let gaps: Vec<f64> = cell_times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        features.push(gaps.iter().cloned().fold(0.0, f64::max));
+        features.push(mean(&gaps));
+        features.push(stdev(&gaps));
+        features.push(percentile(&gaps, 75.0));
+    }
+    
+    for cell_times in time_sets.iter() {
+        for p in [25.0, 50.0, 75.0, 100.0].iter() {
+            features.push(percentile(cell_times, *p));
+        }
+    }
+    
+    let mut per_second: Vec<f64> = Vec::new();
+    for cell in trace.iter() {
+        let second = (cell.time / 1000000.0) as usize;
+        if per_second.len() <= second {
+            per_second.resize(second + 1, 0.0);
+        }
+        per_second[second] += 1.0;
+    }
+    features.push(mean(&per_second));
+    features.push(stdev(&per_second));
+    features.push(if per_second.is_empty() { 0.0 } else { per_second.iter().cloned().fold(f64::INFINITY, f64::min) });
+    features.push(per_second.iter().cloned().fold(0.0, f64::max));
+    features.push(percentile(&per_second, 50.0));
+    
+    let first = &trace[..trace.len().min(KFP_EDGE_CELLS)];
+    let last = &trace[trace.len() - trace.len().min(KFP_EDGE_CELLS)..];
+    for cells in [first, last].iter() {
+        let incoming = cells.iter().filter(|cell| cell.relay_sending).count();
+        features.push(incoming as f64);
+        features.push((cells.len() - incoming) as f64);
+    }
+    
+    let bursts = outgoing_bursts(trace);
+    features.push(bursts.len() as f64);
+    features.push(bursts.iter().max().copied().unwrap_or(0) as f64);
+    features.push(mean(&bursts.iter().map(|&burst| burst as f64).collect::<Vec<f64>>()));
+    for threshold in [5, 10, 20].iter() {
+        features.push(bursts.iter().filter(|&burst| burst > threshold).count() as f64);
+    }
+    
+    features.append(&mut sum_groups(&concentrations, KFP_ALT_CONCENTRATION));
+    features.append(&mut sum_groups(&per_second, KFP_ALT_PER_SECOND));
+    
+    return features;
+}
+
+// Burst statistics, where a burst is a run of cells in the same direction: for outgoing
+// then incoming bursts, their number, mean, standard deviation, median and maximum size,
+// and the mean and maximum number of cells per second within a burst (timed traces).
+pub fn burst_features(trace: &[Cell]) -> Vec<f64> {
+    let mut features: Vec<f64> = Vec::new();
+    
+    // (direction, cells, duration in seconds) of each burst
+    let mut bursts: Vec<(bool, usize, f64)> = Vec::new();
+    let mut start = 0.0;
+    for cell in trace.iter() {
+        if let Some(burst) = bursts.last_mut() {
+            if burst.0 == cell.relay_sending {
+                burst.1 += 1;
+                burst.2 = (cell.time - start) / 1000000.0;
+                continue;
+            }
+        }
+        bursts.push((cell.relay_sending, 1, 0.0));
+        start = cell.time;
+    }
+    
+    for relay_sending in [false, true].iter() {
+        let sizes: Vec<f64> = bursts.iter().filter(|burst| burst.0 == *relay_sending).map(|burst| burst.1 as f64).collect();
+        let rates: Vec<f64> = bursts.iter()
+            .filter(|burst| burst.0 == *relay_sending && burst.2 > 0.0)
+            .map(|burst| burst.1 as f64 / burst.2)
+            .collect();
+        
+        features.push(sizes.len() as f64);
+        features.push(mean(&sizes));
+        features.push(stdev(&sizes));
+        features.push(percentile(&sizes, 50.0));
+        features.push(sizes.iter().cloned().fold(0.0, f64::max));
+        features.push(mean(&rates));
+        features.push(rates.iter().cloned().fold(0.0, f64::max));
+    }
+    
+    return features;
+}
+
+// Split values into the given number of contiguous groups of nearly equal length, and
+// sum each group; groups are empty (0) when there are fewer values than groups.
+fn sum_groups(values: &[f64], groups: usize) -> Vec<f64> {
+    return (0..groups).map(|g| {
+        let start = g * values.len() / groups;
+        let end = (g + 1) * values.len() / groups;
+        values[start..end].iter().sum()
+    }).collect();
+}
+
+// Sizes of the runs of outgoing cells
+fn outgoing_bursts(trace: &[Cell]) -> Vec<usize> {
+    let mut bursts: Vec<usize> = Vec::new();
+    let mut size = 0;
+    
+    for cell in trace.iter() {
+        if !cell.relay_sending {
+            size += 1;
+        } else if size > 0 {
+            bursts.push(size);
+            size = 0;
+        }
+    }
+    if size > 0 {
+        bursts.push(size);
+    }
+    
+    return bursts;
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    return values.iter().sum::<f64>() / values.len() as f64;
+}
+
+fn stdev(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mean = mean(values);
+    return (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+}
+
+// Percentile of values, interpolating between the closest ranks
+fn percentile(values: &[f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    return sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Two outgoing cells, three incoming and one outgoing, half a second apart
+    fn small_trace() -> Vec<Cell> {
+        return [false, false, true, true, true, false].iter().enumerate().map(|(i, &relay_sending)| Cell {
+            time: i as f64 * 500000.0,
+            relay_sending: relay_sending,
+        }).collect();
+    }
+    
+    #[test]
+    fn feature_sets_have_fixed_lengths() {
+        for trace in [Vec::new(), small_trace()].iter() {
+            assert_eq!(knn_features(trace).len(), 730);
+            assert_eq!(cumul_features(trace).len(), 102);
+            assert_eq!(kfp_features(trace).len(), 143);
+            assert_eq!(burst_features(trace).len(), 14);
+        }
+    }
+    
+    #[test]
+    fn knn_features_of_small_trace() {
+        let features = knn_features(&small_trace());
+        assert_eq!(features[..10], [6.0, 3.0, 3.0, 2.5, 2.0, 2.0, 1.5, 0.0, 0.0, 0.0]);
+        assert_eq!(features[10..17], [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, MISSING]);
+        assert_eq!(features[30..34], [0.0, 1.0, 5.0, MISSING]);
+        assert_eq!(features[330..334], [2.0, 3.0, 4.0, MISSING]);
+        assert_eq!(features[630..632], [3.0, MISSING]);
+    }
+    
+    #[test]
+    fn cumul_features_interpolate_cumulative_directions() {
+        // Cumulative sum 1, 2, 1, 0, -1, 0
+        let features = cumul_features(&small_trace());
+        assert_eq!(features[..3], [3.0, 3.0, 1.0]);
+        assert_eq!(features[101], 0.0);
+        assert!(features[2..].iter().all(|value| (-1.0..=2.0).contains(value)));
+        
+        // A single cell is repeated at every point
+        let single = cumul_features(&small_trace()[..1]);
+        assert!(single[2..].iter().all(|&value| value == 1.0));
+        
+        assert!(cumul_features(&[]).iter().all(|&value| value == 0.0));
+    }
+    
+    #[test]
+    fn kfp_features_of_small_trace() {
+        let features = kfp_features(&small_trace());
+        assert_eq!(features[..5], [6.0, 3.0, 3.0, 0.5, 0.5]);
+        // Outgoing positions 0, 1, 5 and incoming positions 2, 3, 4
+        assert_eq!(features[5], 2.0);
+        assert_eq!(features[7], 3.0);
+        assert!((features[8] - (2.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        // Percentiles of all cell times, then cells per second and the edge cells
+        assert_eq!(features[26..30], [0.625, 1.25, 1.875, 2.5]);
+        assert_eq!(features[38], 2.0);
+        assert_eq!(features[43..47], [3.0, 3.0, 3.0, 3.0]);
+        // Outgoing bursts of 2 and 1 cells
+        assert_eq!(features[47..53], [2.0, 2.0, 1.5, 0.0, 0.0, 0.0]);
+        // Grouped sums keep the totals
+        assert_eq!(features[53..123].iter().sum::<f64>(), 3.0);
+        assert_eq!(features[123..].iter().sum::<f64>(), 6.0);
+    }
+    
+    #[test]
+    fn burst_features_of_small_trace() {
+        // Outgoing bursts of 2 cells over 0.5 s and 1 cell, one incoming burst of 3 cells over 1 s
+        let features = burst_features(&small_trace());
+        assert_eq!(features[..7], [2.0, 1.5, 0.5, 1.5, 2.0, 4.0, 4.0]);
+        assert_eq!(features[7..], [1.0, 3.0, 0.0, 3.0, 3.0, 3.0, 3.0]);
+    }
+    
+    #[test]
+    fn sum_groups_splits_values_evenly() {
+        assert_eq!(sum_groups(&[1.0, 2.0, 3.0, 4.0], 2), vec![3.0, 7.0]);
+        assert_eq!(sum_groups(&[1.0], 3), vec![0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,247 @@
+// Generators -- runs the defense generator binaries and collects the machines they print,
+// parses the parameter values they are swept over, and searches them for a target overhead
+
+use std::env;
+use std::str::FromStr;
+use std::process::Command;
+
+use maybenot::machine::Machine;
+
+// A defense generator: its binary, the names of its positional parameters, and those of
+// them that the generator parses as whole numbers
+pub struct Defense {
+    pub name: &'static str,
+    pub binary: &'static str,
+    pub params: &'static [&'static str],
+    pub integers: &'static [&'static str],
+}
+
+pub static DEFENSES: [Defense; 8] = [
+    Defense { name: "front", binary: "maybenot_front", params: &["W", "N", "states"], integers: &["N", "states"] },
+    Defense { name: "pipelined-front", binary: "pipelined_front", params: &["W", "N", "pipelines", "states"], integers: &["N", "pipelines", "states"] },
+    Defense { name: "regulator", binary: "maybenot_regulator", params: &["R", "D", "T", "U", "cells"], integers: &[] },
+    Defense { name: "tamaraw", binary: "maybenot_tamaraw", params: &["rho_out", "rho_in", "L"], integers: &["L"] },
+    Defense { name: "buflo", binary: "maybenot_buflo", params: &["rho", "tau"], integers: &[] },
+    Defense { name: "cs-buflo", binary: "maybenot_csbuflo", params: &["rho", "levels", "cells"], integers: &["levels"] },
+    Defense { name: "constant-rate", binary: "maybenot_constant_rate", params: &["rate", "idle"], integers: &[] },
+    Defense { name: "delay", binary: "maybenot_delay", params: &["delay", "prob"], integers: &[] },
+];
+
+impl Defense {
+    pub fn is_integer(&self, index: usize) -> bool {
+        return self.integers.contains(&self.params[index]);
+    }
+    
+    // Panic if a value can't be passed to the generator as the given parameter
+    pub fn check_value(&self, index: usize, value: f64) {
+        assert!(!self.is_integer(index) || (value >= 0.0 && value.fract() == 0.0),
+            "Parameter {} of {} must be a whole number, got {}", self.params[index], self.name, value);
+    }
+}
+
+// Serialized machines of a generated defense. Defenses with a single machine (FRONT)
+// run it on both sides.
+#[derive(Clone)]
+pub struct Generated {
+    pub client: String,
+    pub relay: String,
+}
+
+impl Generated {
+    pub fn client_machine(&self) -> Machine {
+        return Machine::from_str(&self.client).expect("Couldn't parse generated machine");
+    }
+    
+    pub fn relay_machine(&self) -> Machine {
+        return Machine::from_str(&self.relay).expect("Couldn't parse generated machine");
+    }
+}
+
+pub fn find_defense(name: &str) -> &'static Defense {
+    return DEFENSES.iter().find(|defense| defense.name == name)
+        .unwrap_or_else(|| panic!("Unknown defense: {}", name));
+}
+
+// Run the generator of a defense with the given parameter values (in the order of
+// defense.params). The generator binaries are expected next to the current one.
+pub fn generate(defense: &Defense, values: &[f64]) -> Generated {
+    assert!(values.len() == defense.params.len(), "Wrong number of parameters for {}", defense.name);
+    for (index, value) in values.iter().enumerate() {
+        defense.check_value(index, *value);
+    }
+    
+    let exe = env::current_exe().expect("Couldn't find current executable");
+    let binary = exe.with_file_name(defense.binary);
+    let output = Command::new(&binary)
+        .args(values.iter().map(|value| value.to_string()))
+        .output()
+        .unwrap_or_else(|_| panic!("Couldn't run {} (build all binaries first)", binary.display()));
+    assert!(output.status.success(), "{} failed: {}", defense.binary, String::from_utf8_lossy(&output.stderr));
+    
+    let mut client: Option<String> = None;
+    let mut relay: Option<String> = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // "<label>: <machine> (<length>)"
+        let (label, rest) = match line.split_once(": ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let machine = rest.split_whitespace().next().unwrap_or("").to_string();
+        
+        match label {
+            "Machine" => {
+                client = Some(machine.clone());
+                relay = Some(machine);
+            }
+            "Client machine" => client = Some(machine),
+            "Relay machine" => relay = Some(machine),
+            _ => {}
+        }
+    }
+    
+    return Generated {
+        client: client.expect("Generator printed no client machine"),
+        relay: relay.expect("Generator printed no relay machine"),
+    };
+}
+
+// Parse a list of parameter values, either "v1,v2,..." or an inclusive range
+// "start:end:step".
+pub fn parse_values(spec: &str) -> Vec<f64> {
+    let range: Vec<&str> = spec.split(':').collect();
+    if range.len() == 1 {
+        return spec.split(',').map(|value| value.parse().expect("Invalid parameter value")).collect();
+    }
+    
+    assert!(range.len() == 3, "Invalid parameter range: {}", spec);
+    let start: f64 = range[0].parse().expect("Invalid range start");
+    let end: f64 = range[1].parse().expect("Invalid range end");
+    let step: f64 = range[2].parse().expect("Invalid range step");
+    assert!(step > 0.0, "Range step must be positive: {}", spec);
+    
+    let mut values: Vec<f64> = Vec::new();
+    let mut i = 0;
+    while start + i as f64 * step <= end + step * 1e-9 {
+        values.push(start + i as f64 * step);
+        i += 1;
+    }
+    return values;
+}
+
+// Search one parameter between low and high for the value with the most overhead that
+// stays within the target, given the overhead of a value. The overhead is assumed to be
+// monotonic in the parameter, in either direction. Both ends are evaluated first: if the
+// whole range meets the target, the end with the most overhead is best, and otherwise the
+// search is a binary search over the low value plus multiples of the resolution. Returns
+// the best value and its overhead, or None if no value meets the target.
+pub fn tune<F: FnMut(f64) -> f64>(low: f64, high: f64, resolution: f64, target: f64, mut evaluate: F) -> Option<(f64, f64)> {
+    // Orient the search so that overhead increases from feasible to infeasible
+    let low_overhead = evaluate(low);
+    let high_overhead = evaluate(high);
+    let (mut feasible, mut infeasible, feasible_overhead) = if low_overhead <= high_overhead {
+        (low, high, low_overhead)
+    } else {
+        (high, low, high_overhead)
+    };
+    
+    if feasible_overhead > target {
+        return None;
+    }
+    if f64::max(low_overhead, high_overhead) <= target {
+        return Some((infeasible, f64::max(low_overhead, high_overhead)));
+    }
+    
+    let mut best = (feasible, feasible_overhead);
+    while (feasible - infeasible).abs() > resolution {
+        let steps = ((feasible - low) + (infeasible - low)) / 2.0 / resolution;
+        let middle = low + steps.round() * resolution;
+        if middle == feasible || middle == infeasible {
+            break;
+        }
+        
+        let overhead = evaluate(middle);
+        if overhead <= target {
+            feasible = middle;
+            best = (middle, overhead);
+        } else {
+            infeasible = middle;
+        }
+    }
+    
+    return Some(best);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parse_values_reads_lists() {
+        assert_eq!(parse_values("500,1000,1700"), vec![500.0, 1000.0, 1700.0]);
+        assert_eq!(parse_values("0.5"), vec![0.5]);
+    }
+    
+    #[test]
+    fn parse_values_includes_range_end() {
+        assert_eq!(parse_values("2:14:4"), vec![2.0, 6.0, 10.0, 14.0]);
+        assert_eq!(parse_values("5:5:1"), vec![5.0]);
+    }
+    
+    #[test]
+    fn parse_values_stops_before_range_end_between_steps() {
+        assert_eq!(parse_values("2:13:4"), vec![2.0, 6.0, 10.0]);
+    }
+    
+    #[test]
+    fn parse_values_tolerates_rounding_of_fractional_steps() {
+        let values = parse_values("0.1:0.3:0.1");
+        assert_eq!(values.len(), 3);
+        assert!((values[2] - 0.3).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn parse_values_of_empty_range_is_empty() {
+        assert!(parse_values("10:5:1").is_empty());
+    }
+    
+    #[test]
+    fn tune_finds_largest_feasible_value_when_overhead_increases() {
+        let best = tune(0.0, 100.0, 1.0, 0.375, |value| value / 100.0);
+        assert_eq!(best, Some((37.0, 0.37)));
+    }
+    
+    #[test]
+    fn tune_finds_smallest_feasible_value_when_overhead_decreases() {
+        let best = tune(0.0, 100.0, 1.0, 0.375, |value| 1.0 - value / 100.0);
+        assert_eq!(best, Some((63.0, 0.37)));
+    }
+    
+    #[test]
+    fn tune_searches_multiples_of_resolution_from_low() {
+        let mut evaluated: Vec<f64> = Vec::new();
+        let best = tune(2.0, 100.0, 5.0, 0.375, |value| {
+            evaluated.push(value);
+            return value / 100.0;
+        });
+        
+        assert_eq!(best, Some((37.0, 0.37)));
+        assert!(evaluated[2..].iter().all(|value| ((value - 2.0) / 5.0).fract() == 0.0), "{:?}", evaluated);
+    }
+    
+    #[test]
+    fn tune_takes_best_end_when_whole_range_meets_target() {
+        let mut evaluations = 0;
+        let best = tune(10.0, 20.0, 1.0, 0.5, |value| {
+            evaluations += 1;
+            return 0.5 - value / 100.0;
+        });
+        
+        assert_eq!(best, Some((10.0, 0.4)));
+        assert_eq!(evaluations, 2);
+    }
+    
+    #[test]
+    fn tune_fails_when_no_value_meets_target() {
+        assert_eq!(tune(10.0, 20.0, 1.0, 0.05, |value| value / 100.0), None);
+    }
+}
@@ -0,0 +1,193 @@
+// kNN -- the k-nearest-neighbor website fingerprinting attack of Wang et al.
+// ("Effective Attacks and Provable Defenses for Website Fingerprinting")
+
+use std::thread;
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::features::MISSING;
+
+pub const RECO_POINTS: usize = 5; // nearest same-site and other-site points used to learn weights
+
+// Distance between two values of a feature; MISSING values do not count
+fn feature_distance(a: f64, b: f64) -> f64 {
+    if a == MISSING || b == MISSING {
+        return 0.0;
+    }
+    return (a - b).abs();
+}
+
+// Weighted L1 distance between two feature vectors
+pub fn distance(a: &[f64], b: &[f64], weights: &[f64]) -> f64 {
+    let mut distance = 0.0;
+    for f in 0..weights.len() {
+        distance += weights[f] * feature_distance(a[f], b[f]);
+    }
+    return distance;
+}
+
+// Learn feature weights as in Wang et al. For each of num_points training points, the
+// weights of the features that let the most other-site points come as close as the
+// nearest same-site points are reduced, and the reduction is handed to the features
+// that do best. Unmonitored traces (None) are one class.
+pub fn learn_weights<R: Rng>(features: &[Vec<f64>], labels: &[Option<usize>], num_points: usize, rng: &mut R) -> Vec<f64> {
+    let num_features = features.first().map(|point| point.len()).unwrap_or(0);
+    let mut weights: Vec<f64> = (0..num_features).map(|_| rng.gen_range(0.5, 1.5)).collect();
+    
+    let mut order: Vec<usize> = (0..features.len()).collect();
+    order.shuffle(rng);
+    
+    for &i in order.iter().take(num_points) {
+        let mut same: Vec<(f64, usize)> = Vec::new();
+        let mut other: Vec<(f64, usize)> = Vec::new();
+        for j in 0..features.len() {
+            if j == i {
+                continue;
+            }
+            let point_distance = distance(&features[i], &features[j], &weights);
+            if labels[j] == labels[i] {
+                same.push((point_distance, j));
+            } else {
+                other.push((point_distance, j));
+            }
+        }
+        if same.is_empty() || other.is_empty() {
+            continue;
+        }
+        
+        same.sort_by(|a, b| a.0.total_cmp(&b.0));
+        other.sort_by(|a, b| a.0.total_cmp(&b.0));
+        same.truncate(RECO_POINTS);
+        other.truncate(RECO_POINTS);
+        
+        // How many other-site points are at most as far as the farthest same-site point,
+        // overall and for each feature
+        let max_same = same.last().unwrap().0;
+        let point_badness = other.iter().filter(|&&(point_distance, _)| point_distance <= max_same).count();
+        
+        let mut badness: Vec<f64> = vec![0.0; num_features];
+        for f in 0..num_features {
+            let max_same_feature = same.iter()
+                .map(|&(_, j)| feature_distance(features[i][f], features[j][f]))
+                .fold(0.0, f64::max);
+            badness[f] = other.iter()
+                .filter(|&&(_, j)| feature_distance(features[i][f], features[j][f]) <= max_same_feature)
+                .count() as f64;
+        }
+        
+        let min_badness = badness.iter().cloned().fold(f64::INFINITY, f64::min);
+        let factor = 0.2 + point_badness as f64 / RECO_POINTS as f64;
+        let mut reduced = 0.0;
+        for f in 0..num_features {
+            if badness[f] != min_badness {
+                let delta = weights[f] * 0.01 * badness[f] / RECO_POINTS as f64 * factor;
+                weights[f] -= delta;
+                reduced += delta;
+            }
+        }
+        
+        let num_best = badness.iter().filter(|&&value| value == min_badness).count();
+        for f in 0..num_features {
+            if badness[f] == min_badness {
+                weights[f] += reduced / num_best as f64;
+            }
+        }
+    }
+    
+    return weights;
+}
+
+// Classify a sample by its k nearest training points. In the open world, a monitored site
+// is only predicted if all k neighbors are of that site, as in Wang et al.; in the closed
+// world, the most common site among the neighbors is predicted, ties going to the nearest.
+pub fn classify(train: &[Vec<f64>], labels: &[Option<usize>], weights: &[f64], sample: &[f64], k: usize, open_world: bool) -> Option<usize> {
+    let mut neighbors: Vec<(f64, usize)> = train.iter().enumerate()
+        .map(|(j, point)| (distance(sample, point, weights), j))
+        .collect();
+    neighbors.sort_by(|a, b| a.0.total_cmp(&b.0));
+    neighbors.truncate(k);
+    
+    if open_world {
+        let first = labels[neighbors.first()?.1];
+        if neighbors.iter().all(|&(_, j)| labels[j] == first) {
+            return first;
+        }
+        return None;
+    }
+    
+    let mut counts: HashMap<Option<usize>, usize> = HashMap::new();
+    for &(_, j) in neighbors.iter() {
+        *counts.entry(labels[j]).or_insert(0) += 1;
+    }
+    
+    let mut best: Option<(Option<usize>, usize)> = None;
+    for &(_, j) in neighbors.iter() {
+        let count = counts[&labels[j]];
+        if best.map_or(true, |(_, best_count)| count > best_count) {
+            best = Some((labels[j], count));
+        }
+    }
+    return best.and_then(|(label, _)| label);
+}
+
+// Classify samples in parallel on the given number of threads.
+pub fn classify_all(train: &[Vec<f64>], labels: &[Option<usize>], weights: &[f64], samples: &[Vec<f64>], k: usize, open_world: bool, threads: usize) -> Vec<Option<usize>> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    
+    let chunk_size = (samples.len() + threads.max(1) - 1) / threads.max(1);
+    return thread::scope(|scope| {
+        let handles: Vec<_> = samples.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+            chunk.iter()
+                .map(|sample| classify(train, labels, weights, sample, k, open_world))
+                .collect::<Vec<Option<usize>>>()
+        })).collect();
+        
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Two sites of three points each, far apart, and one unmonitored point between them
+    fn training_set() -> (Vec<Vec<f64>>, Vec<Option<usize>>) {
+        let train = vec![
+            vec![0.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0],
+            vec![10.0, 10.0], vec![11.0, 10.0], vec![10.0, 11.0],
+            vec![5.0, 5.0],
+        ];
+        let labels = vec![Some(0), Some(0), Some(0), Some(1), Some(1), Some(1), None];
+        return (train, labels);
+    }
+    
+    #[test]
+    fn distance_skips_missing_values() {
+        // 1 * |1 - 4| + 2 * |2 - 0|, and the missing feature does not count
+        assert_eq!(distance(&[1.0, 2.0, MISSING], &[4.0, 0.0, 5.0], &[1.0, 2.0, 3.0]), 7.0);
+    }
+    
+    #[test]
+    fn closed_world_picks_the_nearest_site() {
+        let (train, labels) = training_set();
+        let weights = [1.0, 1.0];
+        
+        assert_eq!(classify(&train, &labels, &weights, &[0.5, 0.5], 3, false), Some(0));
+        assert_eq!(classify(&train, &labels, &weights, &[10.5, 10.5], 3, false), Some(1));
+    }
+    
+    #[test]
+    fn open_world_needs_unanimous_neighbors() {
+        let (train, labels) = training_set();
+        let weights = [1.0, 1.0];
+        
+        // All three neighbors are of site 0
+        assert_eq!(classify(&train, &labels, &weights, &[0.2, 0.2], 3, true), Some(0));
+        // The unmonitored point and two of site 0
+        assert_eq!(classify(&train, &labels, &weights, &[3.0, 3.0], 3, true), None);
+    }
+}
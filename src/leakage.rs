@@ -0,0 +1,144 @@
+// Leakage -- information leakage of features about the site, WeFDE-style (Li et al.,
+// "Measuring Information Leakage in Website Fingerprinting Attacks and Defenses")
+
+use std::thread;
+
+use crate::stats::mean_stdev;
+
+const TAIL: f64 = 4.0; // bandwidths of a kernel's tail that count towards the density
+
+// Mutual information (bits) between a feature and the class, where classes are indices
+// 0..num_classes. The density of the feature for each class is a Gaussian kernel density
+// estimate (Silverman's bandwidth), evaluated on a grid of grid_points over the range of
+// the feature, and the mutual information is integrated numerically over the grid:
+//   I(F; C) = sum_c P(c) integral p(f|c) log2(p(f|c) / p(f)) df
+// Bandwidths are at least one grid step, so discrete features are smoothed rather than
+// missed between grid points.
+pub fn feature_leakage(values: &[f64], classes: &[usize], num_classes: usize, grid_points: usize) -> f64 {
+    assert!(grid_points >= 2, "The grid needs at least two points");
+    
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if values.is_empty() || max <= min {
+        return 0.0;
+    }
+    
+    let mut class_values: Vec<Vec<f64>> = vec![Vec::new(); num_classes];
+    for (&value, &class) in values.iter().zip(classes.iter()) {
+        class_values[class].push(value);
+    }
+    
+    let mut bandwidths: Vec<f64> = class_values.iter().map(|values| silverman_bandwidth(values)).collect();
+    let max_bandwidth = bandwidths.iter().cloned().fold((max - min) / grid_points as f64, f64::max);
+    let low = min - TAIL * max_bandwidth;
+    let high = max + TAIL * max_bandwidth;
+    let step = (high - low) / (grid_points - 1) as f64;
+    for bandwidth in bandwidths.iter_mut() {
+        *bandwidth = f64::max(*bandwidth, step);
+    }
+    
+    // Density of each class on the grid, normalized to integrate to 1 over the grid
+    let mut densities: Vec<Vec<f64>> = Vec::new();
+    for (values, &bandwidth) in class_values.iter().zip(bandwidths.iter()) {
+        let mut density: Vec<f64> = vec![0.0; grid_points];
+        for &value in values.iter() {
+            let first = ((value - TAIL * bandwidth - low) / step).floor().max(0.0) as usize;
+            let last = (((value + TAIL * bandwidth - low) / step).ceil() as usize).min(grid_points - 1);
+            for g in first..=last {
+                let z = (low + g as f64 * step - value) / bandwidth;
+                density[g] += (-0.5 * z * z).exp();
+            }
+        }
+        
+        let total: f64 = density.iter().sum::<f64>() * step;
+        if total > 0.0 {
+            for d in density.iter_mut() {
+                *d /= total;
+            }
+        }
+        densities.push(density);
+    }
+    
+    let priors: Vec<f64> = class_values.iter().map(|class| class.len() as f64 / values.len() as f64).collect();
+    let mut marginal: Vec<f64> = vec![0.0; grid_points];
+    for (density, &prior) in densities.iter().zip(priors.iter()) {
+        for g in 0..grid_points {
+            marginal[g] += prior * density[g];
+        }
+    }
+    
+    let mut leakage = 0.0;
+    for (density, &prior) in densities.iter().zip(priors.iter()) {
+        for g in 0..grid_points {
+            if density[g] > 0.0 && marginal[g] > 0.0 {
+                leakage += prior * density[g] * step * (density[g] / marginal[g]).log2();
+            }
+        }
+    }
+    
+    // Numerical error can push the estimate slightly outside [0, H(C)]
+    let max_leakage = (num_classes as f64).log2();
+    return leakage.max(0.0).min(max_leakage);
+}
+
+// Leakage of each feature of feature vectors (see feature_leakage), in parallel on the
+// given number of threads.
+pub fn leakage_all(features: &[Vec<f64>], classes: &[usize], num_classes: usize, grid_points: usize, threads: usize) -> Vec<f64> {
+    let num_features = features.first().map(|point| point.len()).unwrap_or(0);
+    if num_features == 0 {
+        return Vec::new();
+    }
+    
+    let indices: Vec<usize> = (0..num_features).collect();
+    let chunk_size = (num_features + threads.max(1) - 1) / threads.max(1);
+    return thread::scope(|scope| {
+        let handles: Vec<_> = indices.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+            chunk.iter().map(|&f| {
+                let values: Vec<f64> = features.iter().map(|point| point[f]).collect();
+                feature_leakage(&values, classes, num_classes, grid_points)
+            }).collect::<Vec<f64>>()
+        })).collect();
+        
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+}
+
+// Silverman's rule of thumb, 1.06 * stdev * n^(-1/5); 0 for fewer than two values
+fn silverman_bandwidth(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    
+    let (_, stdev) = mean_stdev(values);
+    return 1.06 * stdev * (values.len() as f64).powf(-0.2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn constant_feature_leaks_nothing() {
+        let values = [3.0; 100];
+        let classes: Vec<usize> = (0..100).map(|i| i % 2).collect();
+        assert_eq!(feature_leakage(&values, &classes, 2, 1000), 0.0);
+    }
+    
+    #[test]
+    fn separated_classes_leak_one_bit() {
+        // Two equally likely classes whose values are far apart
+        let values: Vec<f64> = (0..200).map(|i| (i % 100) as f64 / 100.0 + if i < 100 { 0.0 } else { 100.0 }).collect();
+        let classes: Vec<usize> = (0..200).map(|i| i / 100).collect();
+        let leakage = feature_leakage(&values, &classes, 2, 1000);
+        assert!((leakage - 1.0).abs() < 0.01, "Expected 1 bit, got {}", leakage);
+    }
+    
+    #[test]
+    fn identical_classes_leak_almost_nothing() {
+        // Both classes spread evenly over the same range
+        let values: Vec<f64> = (0..200).map(|i| i as f64 / 100.0).collect();
+        let classes: Vec<usize> = (0..200).map(|i| i % 2).collect();
+        let leakage = feature_leakage(&values, &classes, 2, 1000);
+        assert!(leakage < 0.05, "Expected almost no leakage, got {}", leakage);
+    }
+}
@@ -0,0 +1,14 @@
+// Maybenot defenses -- code shared by the defense generators and the evaluation tools
+// Code from the paper "State Machine Frameworks for Website Fingerprinting Defenses: Maybe Not"
+
+pub mod trace;
+pub mod stats;
+pub mod machines;
+pub mod wtfpad;
+pub mod surakav;
+pub mod simulator;
+pub mod overhead;
+pub mod generate;
+pub mod features;
+pub mod knn;
+pub mod leakage;
@@ -0,0 +1,252 @@
+// Machines -- distributions and state builders shared by the generators, most of them for
+// machines that, once started, hold back all cells with infinite blocking and send cells in
+// place of padding (Tamaraw, BuFLO, CS-BuFLO, Palette and Surakav)
+
+use std::collections::HashMap;
+use std::f64::INFINITY;
+
+use maybenot::{
+event::Event,
+state::State,
+dist::{Dist, DistType}
+};
+
+use crate::trace::TOR_CELL_SIZE;
+
+// Uniform distribution between low and high.
+pub fn uniform_dist(low: f64, high: f64) -> Dist {
+    return Dist {
+        dist: DistType::Uniform,
+        param1: low,
+        param2: high,
+        start: 0.0,
+        max: 0.0,
+    };
+}
+
+// Distribution that always samples the given value.
+pub fn constant_dist(value: f64) -> Dist {
+    return uniform_dist(value, value);
+}
+
+// Parse a distribution specification, <dist>:<param1>:<param2>[:<start>:<max>].
+pub fn parse_dist(spec: &str) -> Dist {
+    let parts: Vec<&str> = spec.split(':').collect();
+    assert!(parts.len() == 3 || parts.len() == 5, "Invalid distribution: {}", spec);
+    
+    let dist_type = match parts[0] {
+        "uniform"   => DistType::Uniform,
+        "normal"    => DistType::Normal,
+        "lognormal" => DistType::LogNormal,
+        "binomial"  => DistType::Binomial,
+        "geometric" => DistType::Geometric,
+        "pareto"    => DistType::Pareto,
+        "poisson"   => DistType::Poisson,
+        "weibull"   => DistType::Weibull,
+        "gamma"     => DistType::Gamma,
+        "beta"      => DistType::Beta,
+        _ => panic!("Unknown distribution: {}", parts[0]),
+    };
+    
+    let mut start = 0.0;
+    let mut max = 0.0;
+    if parts.len() == 5 {
+        start = parts[3].parse().expect("Invalid distribution start");
+        max = parts[4].parse().expect("Invalid distribution max");
+    }
+    
+    let dist = Dist {
+        dist: dist_type,
+        param1: parts[1].parse().expect("Invalid distribution param1"),
+        param2: parts[2].parse().expect("Invalid distribution param2"),
+        start: start,
+        max: max,
+    };
+    assert!(is_valid_dist(&dist), "Invalid distribution: {}", spec);
+    
+    return dist;
+}
+
+// Whether Maybenot can sample from a distribution: its parameters are finite and within
+// the range of its type.
+pub fn is_valid_dist(dist: &Dist) -> bool {
+    let finite = [dist.param1, dist.param2, dist.start, dist.max].iter().all(|value| value.is_finite());
+    if !finite || dist.start < 0.0 || dist.max < 0.0 {
+        return false;
+    }
+    
+    return match dist.dist {
+        DistType::Uniform => dist.param1 <= dist.param2,
+        DistType::Normal | DistType::LogNormal => dist.param2 >= 0.0,
+        DistType::Binomial => dist.param1 >= 0.0 && dist.param2 >= 0.0 && dist.param2 <= 1.0,
+        DistType::Geometric => dist.param1 > 0.0 && dist.param1 <= 1.0,
+        DistType::Poisson => dist.param1 >= 0.0,
+        _ => dist.param1 > 0.0 && dist.param2 > 0.0, // Pareto, Weibull, gamma and beta
+    };
+}
+
+// Transition to a single state with probability 1.
+pub fn transition_to(index: usize) -> HashMap<usize, f64> {
+    let mut next: HashMap<usize, f64> = HashMap::new();
+    next.insert(index, 1.0);
+    return next;
+}
+
+// Generate the START state, which waits for the first non-padding cell either way.
+pub fn generate_start_state(next_index: usize, num_states: usize) -> State {
+    return generate_branch_start_state(transition_to(next_index), num_states);
+}
+
+// Generate a START state that moves on to next (e.g. one of several padding schedules,
+// each with its probability) on the first non-padding cell either way.
+pub fn generate_branch_start_state(next: HashMap<usize, f64>, num_states: usize) -> State {
+    // NonPaddingSent, NonPaddingRecv --> next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::NonPaddingSent, next.clone());
+    transitions.insert(Event::NonPaddingRecv, next);
+    
+    return State::new(transitions, num_states);
+}
+
+// Generate a WAIT state, which moves on to next_index once no non-padding cell has been
+// sent or received for the quiet period. Non-padding cells restart the quiet period, and
+// when it passes, a block of zero duration (which holds back no cells, and sends none)
+// triggers BlockingBegin. The machine needs a blocking allowance for this.
+pub fn generate_wait_state(curr_index: usize, next_index: usize, quiet_period: f64, num_states: usize) -> State {
+    // NonPaddingSent, NonPaddingRecv --> WAIT (100%), BlockingBegin --> next (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::NonPaddingSent, transition_to(curr_index));
+    transitions.insert(Event::NonPaddingRecv, transition_to(curr_index));
+    transitions.insert(Event::BlockingBegin, transition_to(next_index));
+    
+    // WAIT state
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    
+    state.timeout = constant_dist(quiet_period);
+    
+    state.action = constant_dist(0.0);
+    
+    return state;
+}
+
+// Generate an IDLE state, which waits for a non-padding cell to be sent, then moves on to next.
+pub fn generate_idle_state(next: HashMap<usize, f64>, num_states: usize) -> State {
+    // NonPaddingSent --> next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::NonPaddingSent, next);
+    
+    return State::new(transitions, num_states);
+}
+
+// Generate a BLOCK state, which holds back all cells until the machine ends, and moves on
+// to next after the timeout (when the blocking it re-arms begins).
+pub fn generate_block_state(next: HashMap<usize, f64>, timeout: f64, num_states: usize) -> State {
+    // BlockingBegin --> next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::BlockingBegin, next);
+    
+    // BLOCK state
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    state.bypass = true;
+    state.replace = true;
+    
+    state.timeout = constant_dist(timeout);
+    
+    state.action = constant_dist(INFINITY);
+    
+    return state;
+}
+
+// Generate a PAD state, which sends a cell (padding, or non-padding in its place) after
+// each timeout, with the given transitions.
+pub fn generate_pad_state(transitions: HashMap<Event, HashMap<usize, f64>>, timeout: &Dist, num_states: usize) -> State {
+    let mut state = State::new(transitions, num_states);
+    state.bypass = true;
+    state.replace = true;
+    
+    state.timeout = timeout.clone();
+    
+    state.action = constant_dist(TOR_CELL_SIZE);
+    
+    return state;
+}
+
+// Generate a state that sends a cell after the timeout and tells whether it was padding. A
+// non-padding cell sent in place of padding is seen as NonPaddingSent, then PaddingSent: the
+// state moves to data_index (if any) on NonPaddingSent, so PaddingSent here means the cell
+// was padding and moves to padding_index. The state at data_index (data_index is None) moves
+// on to padding_index on the PaddingSent that follows.
+pub fn generate_cell_state(data_index: Option<usize>, padding_index: usize, timeout: &Dist, num_states: usize) -> State {
+    // PaddingSent --> padding_index (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::PaddingSent, transition_to(padding_index));
+    
+    // NonPaddingSent --> data_index (100%)
+    if let Some(data_index) = data_index {
+        transitions.insert(Event::NonPaddingSent, transition_to(data_index));
+    }
+    
+    return generate_pad_state(transitions, timeout, num_states);
+}
+
+// Generate a SEND state, which sends num_cells cells (padding, or non-padding in its
+// place) with the given timeout between them, then moves on to next.
+pub fn generate_send_state(num_cells: f64, timeout: &Dist, curr_index: usize, next: HashMap<usize, f64>, num_states: usize) -> State {
+    // PaddingSent --> SEND (100%), LimitReached --> next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::PaddingSent, transition_to(curr_index));
+    transitions.insert(Event::LimitReached, next);
+    
+    // SEND state
+    let mut state = generate_pad_state(transitions, timeout, num_states);
+    
+    state.limit = constant_dist(num_cells);
+    
+    return state;
+}
+
+// Generate a RECV state, which blocks until num_cells cells have been received, then
+// moves on to next.
+pub fn generate_recv_state(num_cells: f64, curr_index: usize, next: HashMap<usize, f64>, num_states: usize) -> State {
+    // NonPaddingRecv, PaddingRecv --> RECV (100%), LimitReached --> next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::NonPaddingRecv, transition_to(curr_index));
+    transitions.insert(Event::PaddingRecv, transition_to(curr_index));
+    transitions.insert(Event::LimitReached, next);
+    
+    // RECV state
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    state.bypass = true;
+    state.replace = true;
+    
+    state.timeout = constant_dist(0.0);
+    
+    state.action = constant_dist(INFINITY);
+    
+    state.limit = constant_dist(num_cells);
+    
+    return state;
+}
+
+// Generate the UNBLOCK state, which replaces the infinite blocking with blocking that ends
+// immediately, then ends the machine.
+pub fn generate_unblock_state(num_states: usize) -> State {
+    // BlockingBegin --> StateEnd (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::BlockingBegin, transition_to(num_states + 1));
+    
+    // UNBLOCK state
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    state.bypass = true;
+    state.replace = true;
+    
+    state.timeout = constant_dist(0.0);
+    
+    state.action = constant_dist(0.0);
+    
+    return state;
+}
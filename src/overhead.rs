@@ -0,0 +1,172 @@
+// Overhead -- bandwidth and latency overhead of machines over a dataset of traces
+
+use rand::Rng;
+
+use maybenot::machine::Machine;
+
+use crate::trace::Cell;
+use crate::simulator::{simulate, SimConfig, Simulation};
+
+pub const PERCENTILES: [f64; 4] = [25.0, 75.0, 90.0, 95.0];
+
+// Cells and load time of one defended trace, and the load time without the defense
+#[derive(Clone, Copy)]
+pub struct TraceOverhead {
+    pub client_real: usize,
+    pub client_padding: usize,
+    pub relay_real: usize,
+    pub relay_padding: usize,
+    pub load_time: f64,      // time of the last non-padding cell, microseconds
+    pub base_load_time: f64, // the same, simulated without machines
+    pub undelivered: usize,
+}
+
+impl TraceOverhead {
+    pub fn client(&self) -> f64 {
+        return ratio(self.client_padding, self.client_real);
+    }
+    
+    pub fn relay(&self) -> f64 {
+        return ratio(self.relay_padding, self.relay_real);
+    }
+    
+    pub fn total(&self) -> f64 {
+        return ratio(self.client_padding + self.relay_padding, self.client_real + self.relay_real);
+    }
+    
+    // None for untimed traces, which have no load time
+    pub fn latency(&self) -> Option<f64> {
+        if self.base_load_time <= 0.0 {
+            return None;
+        }
+        return Some((self.load_time - self.base_load_time) / self.base_load_time);
+    }
+}
+
+// Summary of one overhead over a dataset. The aggregate is the overhead of the dataset
+// as a whole (e.g., all padding cells over all real cells), as reported in the FRONT,
+// RegulaTor and Surakav papers; the rest are over the per-trace overheads.
+#[derive(Clone)]
+pub struct Stats {
+    pub aggregate: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub percentiles: Vec<(f64, f64)>, // (percentile, overhead) for each of PERCENTILES
+}
+
+#[derive(Clone)]
+pub struct Summary {
+    pub num_traces: usize,
+    pub incomplete: usize, // traces with non-padding cells that were not delivered
+    pub client: Stats,     // padding cells / real cells, client to relay
+    pub relay: Stats,      // padding cells / real cells, relay to client
+    pub total: Stats,      // padding cells / real cells, both directions
+    pub latency: Stats,    // load time increase / undefended load time (timed traces only)
+}
+
+fn ratio(num: usize, denom: usize) -> f64 {
+    if denom == 0 {
+        return 0.0;
+    }
+    return num as f64 / denom as f64;
+}
+
+// Count the cells of a simulated trace, and find its load time.
+fn count_cells(simulation: &Simulation) -> TraceOverhead {
+    let mut overhead = TraceOverhead {
+        client_real: 0,
+        client_padding: 0,
+        relay_real: 0,
+        relay_padding: 0,
+        load_time: 0.0,
+        base_load_time: 0.0,
+        undelivered: simulation.undelivered,
+    };
+    
+    for cell in simulation.cells.iter() {
+        match (cell.relay_sending, cell.padding) {
+            (false, false) => overhead.client_real += 1,
+            (false, true) => overhead.client_padding += 1,
+            (true, false) => overhead.relay_real += 1,
+            (true, true) => overhead.relay_padding += 1,
+        }
+        if !cell.padding {
+            overhead.load_time = f64::max(overhead.load_time, cell.time);
+        }
+    }
+    
+    return overhead;
+}
+
+// Simulate the machines over each trace, and over each trace without machines for the
+// undefended load time.
+pub fn measure<R: Rng>(client_machines: &[Machine], relay_machines: &[Machine], traces: &[Vec<Cell>], config: SimConfig, rng: &mut R) -> Vec<TraceOverhead> {
+    let mut overheads: Vec<TraceOverhead> = Vec::new();
+    
+    for trace in traces.iter() {
+        let baseline = simulate(&[], &[], trace, config, rng);
+        let simulation = simulate(client_machines, relay_machines, trace, config, rng);
+        
+        let mut overhead = count_cells(&simulation);
+        overhead.base_load_time = count_cells(&baseline).load_time;
+        overheads.push(overhead);
+    }
+    
+    return overheads;
+}
+
+// Percentile of sorted values, interpolating between the closest ranks
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    return sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64);
+}
+
+fn stats(aggregate: f64, values: &[f64]) -> Stats {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    
+    let mut mean = 0.0;
+    if !sorted.is_empty() {
+        mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    }
+    
+    return Stats {
+        aggregate: aggregate,
+        mean: mean,
+        median: percentile(&sorted, 50.0),
+        percentiles: PERCENTILES.iter().map(|&p| (p, percentile(&sorted, p))).collect(),
+    };
+}
+
+pub fn summarize(overheads: &[TraceOverhead]) -> Summary {
+    let sum = |f: fn(&TraceOverhead) -> usize| overheads.iter().map(f).sum::<usize>();
+    let client_real = sum(|o| o.client_real);
+    let client_padding = sum(|o| o.client_padding);
+    let relay_real = sum(|o| o.relay_real);
+    let relay_padding = sum(|o| o.relay_padding);
+    
+    let timed: Vec<&TraceOverhead> = overheads.iter().filter(|o| o.latency().is_some()).collect();
+    let load_time: f64 = timed.iter().map(|o| o.load_time).sum();
+    let base_load_time: f64 = timed.iter().map(|o| o.base_load_time).sum();
+    let mut latency = 0.0;
+    if base_load_time > 0.0 {
+        latency = (load_time - base_load_time) / base_load_time;
+    }
+    
+    let values = |f: fn(&TraceOverhead) -> f64| overheads.iter().map(f).collect::<Vec<f64>>();
+    
+    return Summary {
+        num_traces: overheads.len(),
+        incomplete: overheads.iter().filter(|o| o.undelivered > 0).count(),
+        client: stats(ratio(client_padding, client_real), &values(|o| o.client())),
+        relay: stats(ratio(relay_padding, relay_real), &values(|o| o.relay())),
+        total: stats(ratio(client_padding + relay_padding, client_real + relay_real), &values(|o| o.total())),
+        latency: stats(latency, &timed.iter().filter_map(|o| o.latency()).collect::<Vec<f64>>()),
+    };
+}
@@ -0,0 +1,513 @@
+// Trace-driven simulator -- runs Maybenot machines at the client and the relay over
+// an undefended cell trace, and returns the defended trace as seen by the client
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use maybenot::{
+machine::Machine,
+framework::{Action, Framework, MachineId, TriggerEvent}
+};
+
+use crate::trace::{Cell, TOR_CELL_SIZE};
+
+const MTU: u16 = 1500;
+
+const CLIENT: usize = 0;
+const RELAY: usize  = 1;
+
+// Network and stopping parameters of a simulation
+#[derive(Clone, Copy)]
+pub struct SimConfig {
+    pub delay: f64,  // one-way delay between client and relay, microseconds
+    pub jitter: f64, // maximum extra delay added to each cell, microseconds
+    pub linger: f64, // time simulated after the last cell of the undefended trace, microseconds
+}
+
+// A cell in a defended trace, as seen by the client
+#[derive(Clone, Copy)]
+pub struct SimCell {
+    pub time: f64,           // microseconds from the start of the simulation
+    pub relay_sending: bool, // direction: relay to client (true) or client to relay (false)
+    pub padding: bool,       // padding (true) or non-padding (false)
+}
+
+// Result of a simulation
+pub struct Simulation {
+    pub cells: Vec<SimCell>, // defended trace, as seen by the client
+    pub undelivered: usize,  // non-padding cells still queued or in flight when the simulation stopped
+}
+
+impl Simulation {
+    // The defended trace without the padding flags, e.g., for trace::write_trace
+    pub fn trace(&self) -> Vec<Cell> {
+        return self.cells.iter().map(|cell| Cell {
+            time: cell.time,
+            relay_sending: cell.relay_sending,
+        }).collect();
+    }
+}
+
+// Action scheduled by a machine, waiting for its timeout
+#[derive(Clone, Copy)]
+enum Pending {
+    Padding { bypass: bool, replace: bool },
+    Block { duration: f64, bypass: bool, replace: bool },
+}
+
+enum EventKind {
+    Queue(usize),                 // a non-padding cell is queued at an endpoint
+    Arrive(usize, bool),          // a cell (padding if true) arrives at an endpoint
+    Timer(usize, MachineId, u64), // an action of a machine at an endpoint times out
+    BlockEnd(usize, u64),         // blocking at an endpoint ends
+}
+
+struct Event {
+    time: f64,
+    seq: u64,
+    kind: EventKind,
+}
+
+// Events are ordered by time, then by the order they were scheduled in; the heap is
+// a max-heap, so the order is reversed.
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other.time.total_cmp(&self.time)
+            .then_with(|| other.seq.cmp(&self.seq));
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Event {}
+
+struct Endpoint {
+    framework: Framework,
+    queued: usize,                // non-padding cells waiting to be sent
+    waiting: VecDeque<MachineId>, // padding cells waiting for blocking to end
+    blocked_until: f64,
+    block_bypass: bool,
+    block_generation: u64,
+    timers: HashMap<MachineId, (u64, Pending)>,
+    last_arrival: f64,            // at the other endpoint, to keep cells in order
+}
+
+struct Simulator<'a, R: Rng> {
+    config: SimConfig,
+    rng: &'a mut R,
+    start: Instant,
+    now: f64,
+    seq: u64,
+    generation: u64,
+    events: BinaryHeap<Event>,
+    endpoints: Vec<Endpoint>,
+    cells: Vec<SimCell>,
+}
+
+// Parse machines given as "none", a comma-separated list of serialized machines, or a
+// file containing such a list.
+pub fn parse_machines(spec: &str) -> Vec<Machine> {
+    if spec == "none" {
+        return Vec::new();
+    }
+    
+    let mut text = spec.to_string();
+    if Path::new(spec).is_file() {
+        text = fs::read_to_string(spec).expect("Couldn't read machine file");
+    }
+    
+    return text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|machine| !machine.is_empty())
+        .map(|machine| Machine::from_str(machine).expect("Couldn't parse machine"))
+        .collect();
+}
+
+// Simulate the client and relay machines over an undefended trace. Client cells are
+// queued at the client at their time in the trace; relay cells are queued at the relay
+// one delay earlier, so that they would reach the client on time without a defense.
+pub fn simulate<R: Rng>(client_machines: &[Machine], relay_machines: &[Machine], trace: &[Cell], config: SimConfig, rng: &mut R) -> Simulation {
+    let start = Instant::now();
+    let mut sim = Simulator {
+        config: config,
+        rng: rng,
+        start: start,
+        now: 0.0,
+        seq: 0,
+        generation: 0,
+        events: BinaryHeap::new(),
+        endpoints: vec![new_endpoint(client_machines, start), new_endpoint(relay_machines, start)],
+        cells: Vec::new(),
+    };
+    
+    let mut end_time = 0.0;
+    for cell in trace.iter() {
+        if cell.relay_sending {
+            sim.schedule(f64::max(cell.time - config.delay, 0.0), EventKind::Queue(RELAY));
+        } else {
+            sim.schedule(cell.time, EventKind::Queue(CLIENT));
+        }
+        end_time = f64::max(end_time, cell.time);
+    }
+    end_time += config.linger;
+    
+    while let Some(event) = sim.events.pop() {
+        if event.time > end_time {
+            break;
+        }
+        sim.now = event.time;
+        
+        match event.kind {
+            EventKind::Queue(endpoint) => {
+                sim.endpoints[endpoint].queued += 1;
+                sim.flush(endpoint);
+            }
+            EventKind::Arrive(endpoint, padding) => {
+                if endpoint == CLIENT {
+                    sim.record(true, padding);
+                }
+                let trigger = if padding {
+                    TriggerEvent::PaddingRecv { bytes_recv: TOR_CELL_SIZE as u16 }
+                } else {
+                    TriggerEvent::NonPaddingRecv { bytes_recv: TOR_CELL_SIZE as u16 }
+                };
+                sim.trigger(endpoint, &[trigger]);
+            }
+            EventKind::Timer(endpoint, machine, generation) => {
+                let pending = match sim.endpoints[endpoint].timers.get(&machine) {
+                    Some(&(current, pending)) if current == generation => pending,
+                    _ => continue,
+                };
+                sim.endpoints[endpoint].timers.remove(&machine);
+                
+                match pending {
+                    Pending::Padding { bypass, replace } => sim.pad(endpoint, machine, bypass, replace),
+                    Pending::Block { duration, bypass, replace } => sim.block(endpoint, machine, duration, bypass, replace),
+                }
+            }
+            EventKind::BlockEnd(endpoint, generation) => {
+                if sim.endpoints[endpoint].block_generation != generation {
+                    continue;
+                }
+                sim.endpoints[endpoint].blocked_until = 0.0;
+                sim.trigger(endpoint, &[TriggerEvent::BlockingEnd]);
+                sim.flush(endpoint);
+            }
+        }
+    }
+    
+    // Cells still in flight were sent, but never arrived
+    let in_flight = sim.events.iter().filter(|event| match event.kind {
+        EventKind::Arrive(_, padding) => !padding,
+        _ => false,
+    }).count();
+    let undelivered = sim.endpoints.iter().map(|endpoint| endpoint.queued).sum::<usize>() + in_flight;
+    
+    let mut cells = sim.cells;
+    cells.sort_by(|a, b| a.time.total_cmp(&b.time));
+    
+    return Simulation {
+        cells: cells,
+        undelivered: undelivered,
+    };
+}
+
+fn new_endpoint(machines: &[Machine], start: Instant) -> Endpoint {
+    let framework = Framework::new(machines.to_vec(), 0.0, 0.0, MTU, start).expect("Couldn't create framework");
+    
+    return Endpoint {
+        framework: framework,
+        queued: 0,
+        waiting: VecDeque::new(),
+        blocked_until: 0.0,
+        block_bypass: false,
+        block_generation: 0,
+        timers: HashMap::new(),
+        last_arrival: 0.0,
+    };
+}
+
+impl<'a, R: Rng> Simulator<'a, R> {
+    fn schedule(&mut self, time: f64, kind: EventKind) {
+        self.seq += 1;
+        self.events.push(Event {
+            time: time,
+            seq: self.seq,
+            kind: kind,
+        });
+    }
+    
+    fn blocked(&self, endpoint: usize) -> bool {
+        return self.now < self.endpoints[endpoint].blocked_until;
+    }
+    
+    fn record(&mut self, relay_sending: bool, padding: bool) {
+        self.cells.push(SimCell {
+            time: self.now,
+            relay_sending: relay_sending,
+            padding: padding,
+        });
+    }
+    
+    // Trigger events at an endpoint, and schedule or cancel the resulting actions.
+    fn trigger(&mut self, endpoint: usize, events: &[TriggerEvent]) {
+        let current_time = self.start + Duration::from_secs_f64(self.now / 1000000.0);
+        
+        let mut actions: Vec<(MachineId, Option<(Duration, Pending)>)> = Vec::new();
+        for action in self.endpoints[endpoint].framework.trigger_events(events, current_time) {
+            match *action {
+                Action::Cancel { machine } => {
+                    actions.push((machine, None));
+                }
+                Action::InjectPadding { timeout, bypass, replace, machine, .. } => {
+                    actions.push((machine, Some((timeout, Pending::Padding { bypass: bypass, replace: replace }))));
+                }
+                Action::BlockOutgoing { timeout, duration, bypass, replace, machine } => {
+                    let duration = duration.as_secs_f64() * 1000000.0;
+                    actions.push((machine, Some((timeout, Pending::Block { duration: duration, bypass: bypass, replace: replace }))));
+                }
+            }
+        }
+        
+        for (machine, action) in actions {
+            match action {
+                None => {
+                    self.endpoints[endpoint].timers.remove(&machine);
+                }
+                Some((timeout, pending)) => {
+                    self.generation += 1;
+                    let generation = self.generation;
+                    self.endpoints[endpoint].timers.insert(machine, (generation, pending));
+                    let time = self.now + timeout.as_secs_f64() * 1000000.0;
+                    self.schedule(time, EventKind::Timer(endpoint, machine, generation));
+                }
+            }
+        }
+    }
+    
+    // Send a cell from an endpoint, scheduling its arrival at the other one.
+    fn send(&mut self, endpoint: usize, padding: bool) {
+        if endpoint == CLIENT {
+            self.record(false, padding);
+        }
+        
+        let mut arrival = self.now + self.config.delay;
+        if self.config.jitter > 0.0 {
+            arrival += self.rng.gen::<f64>() * self.config.jitter;
+        }
+        arrival = f64::max(arrival, self.endpoints[endpoint].last_arrival);
+        self.endpoints[endpoint].last_arrival = arrival;
+        
+        self.schedule(arrival, EventKind::Arrive(1 - endpoint, padding));
+    }
+    
+    // Send the queued cells of an endpoint, unless it is blocking.
+    fn flush(&mut self, endpoint: usize) {
+        if self.blocked(endpoint) {
+            return;
+        }
+        
+        while self.endpoints[endpoint].queued > 0 {
+            self.endpoints[endpoint].queued -= 1;
+            self.send(endpoint, false);
+            self.trigger(endpoint, &[TriggerEvent::NonPaddingSent { bytes_sent: TOR_CELL_SIZE as u16 }]);
+        }
+        
+        while let Some(machine) = self.endpoints[endpoint].waiting.pop_front() {
+            self.send(endpoint, true);
+            self.trigger(endpoint, &[TriggerEvent::PaddingSent { bytes_sent: TOR_CELL_SIZE as u16, machine: machine }]);
+        }
+    }
+    
+    // Send a padding cell, or a queued non-padding cell in its place if replace is set.
+    // Padding only gets through blocking if both the padding and the blocking allow it.
+    fn pad(&mut self, endpoint: usize, machine: MachineId, bypass: bool, replace: bool) {
+        if self.blocked(endpoint) && !(bypass && self.endpoints[endpoint].block_bypass) {
+            self.endpoints[endpoint].waiting.push_back(machine);
+            return;
+        }
+        
+        let padding_sent = TriggerEvent::PaddingSent { bytes_sent: TOR_CELL_SIZE as u16, machine: machine };
+        if replace && self.endpoints[endpoint].queued > 0 {
+            self.endpoints[endpoint].queued -= 1;
+            self.send(endpoint, false);
+            self.trigger(endpoint, &[TriggerEvent::NonPaddingSent { bytes_sent: TOR_CELL_SIZE as u16 }, padding_sent]);
+            return;
+        }
+        
+        self.send(endpoint, true);
+        self.trigger(endpoint, &[padding_sent]);
+    }
+    
+    // Start blocking at an endpoint. Blocking that is already in progress is only
+    // replaced if replace is set or the new blocking ends later.
+    fn block(&mut self, endpoint: usize, machine: MachineId, duration: f64, bypass: bool, replace: bool) {
+        let end = self.now + duration;
+        if !self.blocked(endpoint) || replace || end > self.endpoints[endpoint].blocked_until {
+            self.endpoints[endpoint].blocked_until = end;
+            self.endpoints[endpoint].block_bypass = bypass;
+            self.generation += 1;
+            self.endpoints[endpoint].block_generation = self.generation;
+            let generation = self.generation;
+            self.schedule(end, EventKind::BlockEnd(endpoint, generation));
+        }
+        
+        self.trigger(endpoint, &[TriggerEvent::BlockingBegin { machine: machine }]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    
+    use maybenot::{
+    event::Event as MachineEvent,
+    state::State
+    };
+    
+    use crate::machines::constant_dist;
+    
+    fn config() -> SimConfig {
+        return SimConfig {
+            delay: 10000.0,
+            jitter: 0.0,
+            linger: 1000000.0,
+        };
+    }
+    
+    // Client cells at 0 and 1 ms
+    fn two_client_cells() -> Vec<Cell> {
+        return vec![
+            Cell { time: 0.0, relay_sending: false },
+            Cell { time: 1000.0, relay_sending: false },
+        ];
+    }
+    
+    // A machine that blocks outgoing cells indefinitely (bypassable) after the first
+    // non-padding cell sent, and then sends one padding cell after 5 ms with the given
+    // bypass and replace flags.
+    fn block_then_pad(bypass: bool, replace: bool) -> Machine {
+        let num_states = 3;
+        
+        // START: NonPaddingSent --> BLOCK (100%)
+        let mut nonpadding_sent: HashMap<usize, f64> = HashMap::new();
+        nonpadding_sent.insert(1, 1.0);
+        let mut transitions: HashMap<MachineEvent, HashMap<usize, f64>> = HashMap::new();
+        transitions.insert(MachineEvent::NonPaddingSent, nonpadding_sent);
+        let start = State::new(transitions, num_states);
+        
+        // BLOCK: BlockingBegin --> PAD (100%)
+        let mut blocking_begin: HashMap<usize, f64> = HashMap::new();
+        blocking_begin.insert(2, 1.0);
+        let mut transitions: HashMap<MachineEvent, HashMap<usize, f64>> = HashMap::new();
+        transitions.insert(MachineEvent::BlockingBegin, blocking_begin);
+        let mut block = State::new(transitions, num_states);
+        block.action_is_block = true;
+        block.bypass = true;
+        block.replace = true;
+        block.timeout = constant_dist(0.0);
+        block.action = constant_dist(f64::INFINITY);
+        
+        // PAD: one padding cell
+        let mut pad = State::new(HashMap::new(), num_states);
+        pad.bypass = bypass;
+        pad.replace = replace;
+        pad.timeout = constant_dist(5000.0);
+        pad.action = constant_dist(TOR_CELL_SIZE);
+        
+        return Machine {
+            allowed_padding_bytes: u64::MAX,
+            max_padding_frac: 0.0,
+            allowed_blocked_microsec: u64::MAX,
+            max_blocking_frac: 0.0,
+            states: vec![start, block, pad],
+            include_small_packets: false,
+        };
+    }
+    
+    fn run(client_machines: &[Machine], trace: &[Cell]) -> Simulation {
+        return simulate(client_machines, &[], trace, config(), &mut StdRng::seed_from_u64(0));
+    }
+    
+    #[test]
+    fn events_pop_by_time_then_schedule_order() {
+        let mut events: BinaryHeap<Event> = BinaryHeap::new();
+        for &(time, seq) in [(2.0, 1), (1.0, 3), (1.0, 2), (0.5, 4)].iter() {
+            events.push(Event {
+                time: time,
+                seq: seq,
+                kind: EventKind::Queue(CLIENT),
+            });
+        }
+        
+        let order: Vec<(f64, u64)> = std::iter::from_fn(|| events.pop()).map(|event| (event.time, event.seq)).collect();
+        assert!(order == [(0.5, 4), (1.0, 2), (1.0, 3), (2.0, 1)], "Events popped out of order");
+    }
+    
+    #[test]
+    fn no_machines_only_delays_relay_cells() {
+        // Relay cells are queued one delay early, so every cell is seen at its trace time
+        let trace = [
+            Cell { time: 0.0, relay_sending: false },
+            Cell { time: 30000.0, relay_sending: true },
+            Cell { time: 50000.0, relay_sending: false },
+        ];
+        let simulation = run(&[], &trace);
+        
+        assert_eq!(simulation.undelivered, 0);
+        assert_eq!(simulation.cells.len(), 3);
+        for (cell, expected) in simulation.cells.iter().zip(trace.iter()) {
+            assert_eq!(cell.time, expected.time);
+            assert_eq!(cell.relay_sending, expected.relay_sending);
+            assert!(!cell.padding);
+        }
+    }
+    
+    #[test]
+    fn replace_sends_blocked_cell_in_place_of_padding() {
+        let simulation = run(&[block_then_pad(true, true)], &two_client_cells());
+        
+        // The second cell is held back, then sent by the padding slot at 5 ms
+        let sent: Vec<(f64, bool)> = simulation.cells.iter().map(|cell| (cell.time, cell.padding)).collect();
+        assert!(sent == [(0.0, false), (5000.0, false)], "Unexpected cells: {:?}", sent);
+        assert_eq!(simulation.undelivered, 0);
+    }
+    
+    #[test]
+    fn bypass_sends_padding_through_blocking() {
+        let simulation = run(&[block_then_pad(true, false)], &two_client_cells());
+        
+        // The padding gets through, but the second cell stays blocked
+        let sent: Vec<(f64, bool)> = simulation.cells.iter().map(|cell| (cell.time, cell.padding)).collect();
+        assert!(sent == [(0.0, false), (5000.0, true)], "Unexpected cells: {:?}", sent);
+        assert_eq!(simulation.undelivered, 1);
+    }
+    
+    #[test]
+    fn padding_without_bypass_waits_for_blocking() {
+        let simulation = run(&[block_then_pad(false, true)], &two_client_cells());
+        
+        // Replace alone does not get through blocking, so nothing is sent after the first cell
+        let sent: Vec<(f64, bool)> = simulation.cells.iter().map(|cell| (cell.time, cell.padding)).collect();
+        assert!(sent == [(0.0, false)], "Unexpected cells: {:?}", sent);
+        assert_eq!(simulation.undelivered, 1);
+    }
+}
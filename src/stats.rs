@@ -0,0 +1,30 @@
+// Statistics -- sampling and summary helpers shared by the generators that fit traces
+
+use std::f64::consts::PI;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+
+// Sample from the standard normal distribution (Box-Muller transform)
+pub fn sample_standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>(); // (0, 1]
+    let u2: f64 = rng.gen::<f64>();
+    
+    return (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+}
+
+// Mean and sample standard deviation
+pub fn mean_stdev(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    return (mean, variance.sqrt());
+}
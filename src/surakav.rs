@@ -0,0 +1,1319 @@
+// Surakav -- burst-chain machines built from reference traces, shared by the Surakav and
+// Walkie-Talkie generators
+
+use std::fmt;
+use std::fs::File;
+use std::process;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::collections::HashMap;
+use std::f64::INFINITY;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use maybenot::{
+machine::Machine,
+event::Event,
+state::State,
+dist::{Dist, DistType}
+};
+
+use crate::trace::{try_read_trace, TraceError, TraceFormat, TOR_CELL_SIZE};
+use crate::machines::{uniform_dist, constant_dist, parse_dist, transition_to, generate_start_state, generate_block_state, generate_send_state, generate_recv_state, generate_unblock_state};
+
+const CUTOFF_LENGTH: usize = 8000;    // default, bursts
+const SEND_TIMEOUT: f64    = 5.0;     // default, microseconds between cells in a burst
+const SYNC_TIMEOUT: f64    = 20000.0; // default, microseconds without a cell that end a burst at a divergence point
+
+// Options for generating Surakav machines from reference traces
+pub struct Options {
+    pub cutoff_length: usize, // maximum number of bursts read from each reference
+    pub send_timeout: Dist,   // time between cells sent in a burst
+    pub weights: Vec<f64>,    // relative probability of each reference
+    pub sync_timeout: f64,    // silence after which a burst (or its absence) at a divergence point is decoded
+    pub tail: Tail,           // what to do when the page outlasts the reference
+    pub noise: Noise,         // randomization of burst sizes
+    pub min_burst: usize,     // bursts smaller than this are merged into later bursts (0 to disable)
+    pub max_states: usize,    // maximum number of states per machine (0 for no limit)
+    pub max_bytes: usize,     // maximum size of each serialized machine (0 for no limit)
+    pub trace_format: Option<TraceFormat>, // if set, references are cell-level traces in this format
+    pub burst_gap: f64,       // time between cells in the same direction that starts a new burst, for cell-level traces
+}
+
+// Default options: untimed references, no noise, and machines that end with the reference
+pub fn default_options() -> Options {
+    return Options {
+        cutoff_length: CUTOFF_LENGTH,
+        send_timeout: constant_dist(SEND_TIMEOUT),
+        weights: Vec::new(),
+        sync_timeout: SYNC_TIMEOUT,
+        tail: Tail::End,
+        noise: Noise {
+            quantize: 0.0,
+            jitter: 0.0,
+        },
+        min_burst: 0,
+        max_states: 0,
+        max_bytes: 0,
+        trace_format: None,
+        burst_gap: INFINITY,
+    };
+}
+
+// Build the client and relay machines, with each reference truncated to max_bursts
+// bursts. If trace_lengths is set, the headroom and noise of each reference are reported.
+pub fn build_machines(references: &[(String, Vec<Burst>, bool)], max_bursts: usize, options: &Options, rng: &mut StdRng, trace_lengths: Option<&[usize]>) -> (Machine, Machine) {
+    let mut all_steps: Vec<Vec<Step>> = Vec::with_capacity(references.len());
+    let mut loop_starts: Vec<Option<usize>> = Vec::with_capacity(references.len());
+    let mut reference_cells: Vec<usize> = Vec::with_capacity(references.len());
+    
+    for (ref_file, bursts, timed) in references.iter() {
+        let mut bursts: Vec<Burst> = bursts.iter().take(max_bursts).cloned().collect();
+        let num_bursts = bursts.len();
+        append_tail(&mut bursts, *timed, &options.tail, rng);
+        
+        // The loop tail goes back to the first step of the last K bursts
+        let mut num_tail_bursts = bursts.len();
+        let mut loop_start: Option<usize> = None;
+        if let Tail::Loop(loop_bursts, repeats) = options.tail {
+            let first = num_bursts - loop_bursts.min(num_bursts);
+            if repeats > 0 && first < num_bursts {
+                num_tail_bursts += (num_bursts - first) * repeats;
+                loop_start = Some(generate_steps(&bursts[..first], *timed, &options.send_timeout).len());
+            }
+        }
+        
+        if let Some(trace_lengths) = trace_lengths {
+            report_headroom(ref_file, num_bursts, num_tail_bursts, trace_lengths);
+        }
+        reference_cells.push(bursts.iter().map(|burst| burst.num_cells).sum());
+        all_steps.push(generate_steps(&bursts, *timed, &options.send_timeout));
+        loop_starts.push(loop_start);
+    }
+    
+    // Noise is set per burst state pair, after shared prefixes are merged
+    let (mut nodes, paths) = build_tree(&all_steps, &options.weights);
+    add_loops(&mut nodes, &paths, &loop_starts, &options.weights, &options.tail);
+    apply_noise(&mut nodes, &options.noise, rng);
+    
+    if trace_lengths.is_some() && (options.noise.quantize > 0.0 || options.noise.jitter > 0.0) {
+        for (k, (ref_file, _, _)) in references.iter().enumerate() {
+            report_noise(ref_file, reference_cells[k], &nodes, &paths[k]);
+        }
+    }
+    
+    return generate_machines(&nodes, &options.send_timeout, options.sync_timeout, &options.tail);
+}
+
+// Whether the machines fit the state and size budget with references truncated to
+// max_bursts bursts. Uses a copy of the RNG, so that every call samples the same tail
+// and noise.
+pub fn fits_budget(references: &[(String, Vec<Burst>, bool)], max_bursts: usize, options: &Options, rng: &StdRng) -> bool {
+    if options.max_states == 0 && options.max_bytes == 0 {
+        return true;
+    }
+    
+    let (client_machine, relay_machine) = build_machines(references, max_bursts, options, &mut rng.clone(), None);
+    for machine in [client_machine, relay_machine].iter() {
+        if options.max_states > 0 && machine.states.len() > options.max_states {
+            return false;
+        }
+        if options.max_bytes > 0 && machine.serialize().len() > options.max_bytes {
+            return false;
+        }
+    }
+    
+    return true;
+}
+
+// Report how far truncated references deviate from the originals.
+pub fn report_truncation(references: &[(String, Vec<Burst>, bool)], max_bursts: usize) {
+    let mut dropped_bursts = 0;
+    let mut dropped_cells = 0;
+    let mut total_bursts = 0;
+    let mut total_cells = 0;
+    
+    for (_, bursts, _) in references.iter() {
+        total_bursts += bursts.len();
+        total_cells += bursts.iter().map(|burst| burst.num_cells).sum::<usize>();
+        if bursts.len() > max_bursts {
+            dropped_bursts += bursts.len() - max_bursts;
+            dropped_cells += bursts[max_bursts..].iter().map(|burst| burst.num_cells).sum::<usize>();
+        }
+    }
+    
+    println!("Truncated references to {} bursts to fit the budget: dropped {} of {} bursts and {} of {} cells",
+        max_bursts, dropped_bursts, total_bursts, dropped_cells, total_cells);
+}
+
+// Merge bursts smaller than min_burst cells into the next burst in the same
+// direction, and then merge adjacent bursts in the same direction. Cells left over
+// at the end are added to the last burst in their direction. Returns the merged
+// bursts and the number of cells moved to a later burst.
+//
+// This is not limited to neighbouring bursts: directions usually alternate, so the
+// next burst in the same direction comes after a burst in the other direction, and the
+// cells of a small burst are moved past it. They are then sent after the other side's
+// burst instead of before it, and the bursts around a removed burst are merged, e.g.
+// client 10, relay 1, client 10, relay 50 becomes client 20, relay 51 with min_burst 2.
+// With min_burst 0, only adjacent bursts in the same direction are merged.
+pub fn merge_small_bursts(bursts: &[Burst], min_burst: usize) -> (Vec<Burst>, usize) {
+    let mut merged: Vec<Burst> = Vec::with_capacity(bursts.len());
+    let mut carried: [usize; 2] = [0, 0];
+    let mut moved_cells = 0;
+    
+    for burst in bursts.iter() {
+        let direction = burst.relay_sending as usize;
+        let num_cells = burst.num_cells + carried[direction];
+        if num_cells < min_burst {
+            carried[direction] = num_cells;
+            moved_cells += burst.num_cells;
+            continue;
+        }
+        carried[direction] = 0;
+        
+        let extend = match merged.last() {
+            Some(last) => last.relay_sending == burst.relay_sending,
+            None => false,
+        };
+        if extend {
+            let last = merged.last_mut().unwrap();
+            last.num_cells += num_cells;
+            last.duration = burst.start + burst.duration - last.start;
+        } else {
+            let mut burst = burst.clone();
+            burst.num_cells = num_cells;
+            merged.push(burst);
+        }
+    }
+    
+    for direction in 0..2 {
+        if carried[direction] == 0 {
+            continue;
+        }
+        
+        let relay_sending = direction == 1;
+        match merged.iter_mut().rev().find(|burst| burst.relay_sending == relay_sending) {
+            Some(burst) => burst.num_cells += carried[direction],
+            None => merged.push(Burst {
+                relay_sending: relay_sending,
+                num_cells: carried[direction],
+                start: 0.0,
+                duration: 0.0,
+            }),
+        }
+    }
+    
+    return (merged, moved_cells);
+}
+
+// Parse a send pacing specification into a timeout distribution (microseconds).
+// Accepted forms:
+//   ict:<usec>                          constant inter-cell time, e.g. from a reference trace
+//   bw:<Mbps>                           constant rate at a bandwidth cap
+//   <dist>:<param1>:<param2>[:<start>:<max>]   any Maybenot distribution
+pub fn parse_pacing(spec: &str) -> Dist {
+    let parts: Vec<&str> = spec.split(':').collect();
+    
+    if parts[0] == "ict" || parts[0] == "bw" {
+        assert!(parts.len() == 2, "Invalid send pacing: {}", spec);
+        let value: f64 = parts[1].parse().expect("Invalid send pacing value");
+        assert!(value > 0.0, "Send pacing value must be positive");
+        
+        // One Mbps is one bit per microsecond
+        let mut timeout = value;
+        if parts[0] == "bw" {
+            timeout = TOR_CELL_SIZE * 8.0 / value;
+        }
+        
+        return constant_dist(timeout);
+    }
+    
+    return parse_dist(spec);
+}
+
+// What the machines do once the real page outlasts the reference.
+pub enum Tail {
+    End,                      // stop blocking and end both machines, so the rest of the page is sent undefended
+    Loop(usize, usize),       // go back to the first of the last K bursts of the reference, this many times on average
+    Regulator(f64, f64, f64), // constant-rate padding: client rate, relay rate (cells / sec), duration (sec)
+    Synthetic(usize),         // append this many bursts sampled from the reference's bursts
+}
+
+// Parse a tail specification:
+//   end                                        (default)
+//   loop:<K>:<repeats>
+//   regulator:<client rate>:<relay rate>:<duration>
+//   synthetic:<bursts>
+pub fn parse_tail(spec: &str) -> Tail {
+    let parts: Vec<&str> = spec.split(':').collect();
+    
+    match parts[0] {
+        "end" => {
+            assert!(parts.len() == 1, "Invalid tail: {}", spec);
+            return Tail::End;
+        }
+        "loop" => {
+            assert!(parts.len() == 3, "Invalid tail: {}", spec);
+            let num_bursts: usize = parts[1].parse().expect("Invalid tail loop length");
+            let repeats: usize = parts[2].parse().expect("Invalid tail loop repeats");
+            assert!(num_bursts > 0, "Tail loop length must be positive");
+            return Tail::Loop(num_bursts, repeats);
+        }
+        "regulator" => {
+            assert!(parts.len() == 4, "Invalid tail: {}", spec);
+            let client_rate: f64 = parts[1].parse().expect("Invalid tail client rate");
+            let relay_rate: f64 = parts[2].parse().expect("Invalid tail relay rate");
+            let duration: f64 = parts[3].parse().expect("Invalid tail duration");
+            assert!(client_rate > 0.0 && relay_rate > 0.0, "Tail rates must be positive");
+            return Tail::Regulator(client_rate, relay_rate, duration);
+        }
+        "synthetic" => {
+            assert!(parts.len() == 2, "Invalid tail: {}", spec);
+            return Tail::Synthetic(parts[1].parse().expect("Invalid tail bursts"));
+        }
+        _ => panic!("Unknown tail: {}", spec),
+    }
+}
+
+// Append the synthetic tail to the bursts of a reference. Since the tail is fixed
+// when the machines are generated, both machines follow the same tail. The loop tail
+// is added to the prefix tree instead (see add_loops).
+fn append_tail(bursts: &mut Vec<Burst>, timed: bool, tail: &Tail, rng: &mut StdRng) {
+    if bursts.is_empty() {
+        return;
+    }
+    
+    match *tail {
+        Tail::Synthetic(num_bursts) => {
+            // Alternate directions, drawing each burst (and for timed references,
+            // the gap before it) from the reference's bursts in that direction
+            let mut samples: [Vec<(Burst, f64)>; 2] = [Vec::new(), Vec::new()];
+            let mut prev_end = 0.0;
+            for burst in bursts.iter() {
+                samples[burst.relay_sending as usize].push((burst.clone(), (burst.start - prev_end).max(0.0)));
+                prev_end = burst.start + burst.duration;
+            }
+            
+            let mut relay_sending = !bursts[bursts.len() - 1].relay_sending;
+            for _ in 0..num_bursts {
+                if samples[relay_sending as usize].is_empty() {
+                    relay_sending = !relay_sending;
+                }
+                
+                let direction = &samples[relay_sending as usize];
+                let (sample, gap) = &direction[rng.gen_range(0, direction.len())];
+                let mut burst = sample.clone();
+                if timed {
+                    burst.start = prev_end + gap;
+                    prev_end = burst.start + burst.duration;
+                }
+                bursts.push(burst);
+                relay_sending = !relay_sending;
+            }
+        }
+        Tail::End | Tail::Loop(_, _) | Tail::Regulator(_, _, _) => {}
+    }
+}
+
+// Report the length of a reference, and how many more bursts it has than the
+// undefended traces, if any were given.
+fn report_headroom(ref_file: &str, num_bursts: usize, num_tail_bursts: usize, trace_lengths: &[usize]) {
+    if trace_lengths.is_empty() {
+        println!("Reference {}: {} bursts ({} with tail)", ref_file, num_bursts, num_tail_bursts);
+        return;
+    }
+    
+    let mut headroom: Vec<i64> = trace_lengths.iter().map(|length| (num_tail_bursts as i64) - (*length as i64)).collect();
+    headroom.sort();
+    let outlasting = headroom.iter().filter(|h| **h < 0).count();
+    
+    println!("Reference {}: {} bursts ({} with tail), headroom over {} traces: min {}, median {}, max {}, {:.1}% of traces outlast it",
+        ref_file, num_bursts, num_tail_bursts, headroom.len(), headroom[0], headroom[headroom.len() / 2], headroom[headroom.len() - 1],
+        100.0 * (outlasting as f64) / (headroom.len() as f64));
+}
+
+// Optional randomization of burst sizes, so that machines generated from the same
+// reference are less linkable.
+pub struct Noise {
+    pub quantize: f64, // round sizes up to a power of this base (0 to disable)
+    pub jitter: f64,   // uniform jitter of sizes, as a fraction of the size
+}
+
+// Apply quantization, then return the jitter range around the size, as the smallest
+// and largest number of cells.
+fn noise_range(num_cells: usize, noise: &Noise) -> (usize, usize) {
+    let mut size = num_cells as f64;
+    if noise.quantize > 0.0 && size > 1.0 {
+        let exponent = (size.ln() / noise.quantize.ln() - 1e-9).ceil();
+        size = noise.quantize.powf(exponent).ceil();
+    }
+    
+    let min_cells = (size * (1.0 - noise.jitter)).floor().max(1.0) as usize;
+    let max_cells = ((size * (1.0 + noise.jitter)).round() as usize).max(min_cells);
+    return (min_cells, max_cells);
+}
+
+// Set the size range of each noisy burst state pair. The sending side draws the size
+// from the range on each execution, and the receiving side counts the smallest size,
+// then takes any further cells until none arrives for the sync timeout.
+//
+// Bursts at divergence points are told apart by size (see generate_machines), so they
+// cannot vary: their size is drawn once from the range, when the machines are
+// generated, and written into the machines of both sides. Among siblings in the same
+// direction, a size that is already taken is raised until it is free (sizes without
+// noise are kept).
+fn apply_noise(nodes: &mut [Node], noise: &Noise, rng: &mut StdRng) {
+    if noise.quantize == 0.0 && noise.jitter == 0.0 {
+        return;
+    }
+    
+    // Options at divergence points, including any node a loop tail goes back to
+    let mut fixed: Vec<bool> = vec![false; nodes.len()];
+    for node in nodes.iter() {
+        let num_options = node.children.len() + (node.loop_to.is_some() as usize) + ((node.end_weight > 0.0) as usize);
+        if num_options > 1 {
+            for &child in node.children.iter() {
+                fixed[child] = true;
+            }
+        }
+        if let Some(loop_to) = node.loop_to {
+            fixed[loop_to] = true;
+        }
+    }
+    
+    for node_index in 0..nodes.len() {
+        let children = nodes[node_index].children.clone();
+        let mut taken: Vec<(bool, usize)> = children.iter()
+            .map(|&child| nodes[child].step.as_ref().unwrap())
+            .filter(|step| !step.noisy)
+            .map(|step| (step.relay_sending, step.num_cells))
+            .collect();
+        
+        for &child in children.iter() {
+            let step = nodes[child].step.as_mut().unwrap();
+            if !step.noisy {
+                continue;
+            }
+            
+            let (min_cells, max_cells) = noise_range(step.num_cells, noise);
+            if !fixed[child] {
+                step.num_cells = min_cells;
+                step.max_cells = max_cells;
+                continue;
+            }
+            
+            step.num_cells = rng.gen_range(min_cells, max_cells + 1);
+            while taken.contains(&(step.relay_sending, step.num_cells)) {
+                step.num_cells += 1;
+            }
+            step.max_cells = step.num_cells;
+            taken.push((step.relay_sending, step.num_cells));
+        }
+    }
+}
+
+// Report the cost of burst size noise: the cells in the reference, and the number of
+// cells the machines send for it (the fewest, the most, and the expected number).
+fn report_noise(ref_file: &str, reference_cells: usize, nodes: &[Node], path: &[usize]) {
+    let steps: Vec<&Step> = path.iter().map(|&node_index| nodes[node_index].step.as_ref().unwrap()).collect();
+    let min_cells: usize = steps.iter().map(|step| step.num_cells).sum();
+    let max_cells: usize = steps.iter().map(|step| step.max_cells).sum();
+    let expected_cells = ((min_cells + max_cells) as f64) / 2.0;
+    
+    println!("Reference {}: {} cells, {} to {} with noise, {:.1} expected ({:+.1}%)", ref_file, reference_cells, min_cells, max_cells, expected_cells,
+        100.0 * (expected_cells - (reference_cells as f64)) / (reference_cells as f64));
+}
+
+// A burst of cells in a reference trace.
+#[derive(Clone)]
+pub struct Burst {
+    pub relay_sending: bool, // direction: relay to client (true) or client to relay (false)
+    pub num_cells: usize,
+    pub start: f64,          // offset from the start of the trace (microseconds), timed references only
+    pub duration: f64,       // time from the first to the last cell (microseconds), timed references only
+}
+
+// A pair of burst states: the sending side sends num_cells padding cells with the
+// given timeout, while the receiving side blocks until it has received them. With
+// burst size noise, the sending side sends num_cells to max_cells cells instead.
+#[derive(Clone)]
+struct Step {
+    relay_sending: bool,
+    num_cells: usize,
+    max_cells: usize,
+    timeout: Dist,
+    noisy: bool, // whether burst size noise applies to num_cells
+}
+
+// A node in the prefix tree of references. References that share a prefix of
+// steps also share the burst states for that prefix.
+struct Node {
+    step: Option<Step>,     // None for the root
+    children: Vec<usize>,
+    weight: f64,            // total weight of references passing through this node
+    end_weight: f64,        // total weight of references ending at this node
+    loop_to: Option<usize>, // node that the loop tail goes back to, after this node
+    loop_weight: f64,       // total weight of references going back to it
+}
+
+// Takes the prefix tree of the references and returns Surakav client and relay machines.
+//
+// With more than one reference, the machines pick a reference at random (by weight)
+// at each point where the references diverge. The two machines have no shared
+// randomness, and they send no cells beyond the bursts to agree on the choice: each
+// side tells the other side's options apart by the size of the next burst. The side
+// that sent the last burst (the client at the root) picks first, either one of the
+// bursts it sends next or silence. Once no cell has arrived for sync_timeout, the other
+// side takes the silence as its turn, and picks one of the bursts it sends next or the
+// end of the reference. The end is sent as a burst of a size that no other option has
+// there (the END marker), unless nothing else is left to pick.
+//
+// A burst that might be the start of a larger one is also only decoded after
+// sync_timeout without a cell, and its sender holds back for a little longer before it
+// sends anything else. Silence is timed from the last cell received, so network delay
+// does not matter, but the time between cells of a burst must stay well below
+// sync_timeout: bursts at divergence points are sent no slower than half of it.
+fn generate_machines(nodes: &[Node], send_timeout: &Dist, sync_timeout: f64, tail: &Tail) -> (Machine, Machine) {
+    // Generate machine
+    let client_machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: generate_states(nodes, false, send_timeout, sync_timeout, tail),
+        include_small_packets: false,
+    };
+    let relay_machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: u64::MAX,
+        max_blocking_frac: 0.0,
+        states: generate_states(nodes, true, send_timeout, sync_timeout, tail),
+        include_small_packets: false,
+    };
+    
+    return (client_machine, relay_machine);
+}
+
+// Merge the references into a prefix tree. The root is node 0. Also returns the
+// nodes each reference passes through, after the root. The machines tell options
+// apart by the size of the next burst, so steps of the same size and direction share
+// a node even if their timing differs (keeping the timing of the first).
+fn build_tree(references: &[Vec<Step>], weights: &[f64]) -> (Vec<Node>, Vec<Vec<usize>>) {
+    let mut nodes: Vec<Node> = vec![Node {
+        step: None,
+        children: Vec::new(),
+        weight: 0.0,
+        end_weight: 0.0,
+        loop_to: None,
+        loop_weight: 0.0,
+    }];
+    let mut paths: Vec<Vec<usize>> = Vec::with_capacity(references.len());
+    
+    for (steps, weight) in references.iter().zip(weights.iter()) {
+        let mut curr: usize = 0;
+        let mut path: Vec<usize> = Vec::with_capacity(steps.len());
+        nodes[curr].weight += weight;
+        
+        for step in steps.iter() {
+            let existing = nodes[curr].children.iter()
+                .find(|&&child| same_burst(nodes[child].step.as_ref().unwrap(), step))
+                .cloned();
+            
+            let next = match existing {
+                Some(child) => child,
+                None => {
+                    nodes.push(Node {
+                        step: Some(step.clone()),
+                        children: Vec::new(),
+                        weight: 0.0,
+                        end_weight: 0.0,
+                        loop_to: None,
+                        loop_weight: 0.0,
+                    });
+                    nodes[curr].children.push(nodes.len() - 1);
+                    nodes.len() - 1
+                }
+            };
+            
+            nodes[next].weight += weight;
+            path.push(next);
+            curr = next;
+        }
+        
+        nodes[curr].end_weight += weight;
+        paths.push(path);
+    }
+    
+    return (nodes, paths);
+}
+
+// Whether two steps are the same burst to the receiving side: same direction and size.
+fn same_burst(a: &Step, b: &Step) -> bool {
+    return a.relay_sending == b.relay_sending && a.num_cells == b.num_cells;
+}
+
+// Add the loop tail to the prefix tree: after the last node of a reference, go back to
+// the node of its loop start step with probability repeats / (repeats + 1), and end
+// otherwise. The machines cannot count repeats, so the number of repeats is geometric,
+// with the given mean, and the loop costs no states beyond the reference's own.
+fn add_loops(nodes: &mut [Node], paths: &[Vec<usize>], loop_starts: &[Option<usize>], weights: &[f64], tail: &Tail) {
+    let repeats = match *tail {
+        Tail::Loop(_, repeats) => repeats as f64,
+        _ => return,
+    };
+    
+    for (k, path) in paths.iter().enumerate() {
+        let loop_start = match loop_starts[k] {
+            Some(loop_start) if loop_start < path.len() => loop_start,
+            _ => continue,
+        };
+        
+        // References that end at the same node share their path, and so their loop
+        let last = path[path.len() - 1];
+        let loop_weight = weights[k] * repeats / (repeats + 1.0);
+        nodes[last].loop_to = Some(path[loop_start]);
+        nodes[last].loop_weight += loop_weight;
+        nodes[last].end_weight -= loop_weight;
+    }
+}
+
+// The options after a node: each child, going back for the loop tail, and the end of
+// the reference if any reference ends at the node (None), with their weights. Going
+// back to a burst that a child starts with as well could not be told apart from the
+// child, so the loop ends there instead.
+fn branch_options(nodes: &[Node], node_index: usize) -> Vec<(Option<usize>, f64)> {
+    let mut options: Vec<(Option<usize>, f64)> = Vec::new();
+    let mut end_weight = nodes[node_index].end_weight;
+    
+    for &child in nodes[node_index].children.iter() {
+        options.push((Some(child), nodes[child].weight));
+    }
+    if let Some(loop_to) = nodes[node_index].loop_to {
+        let loop_step = nodes[loop_to].step.as_ref().unwrap();
+        let ambiguous = nodes[node_index].children.iter().any(|&child| same_burst(nodes[child].step.as_ref().unwrap(), loop_step));
+        if ambiguous {
+            end_weight += nodes[node_index].loop_weight;
+        } else {
+            options.push((Some(loop_to), nodes[node_index].loop_weight));
+        }
+    }
+    if end_weight > 0.0 || options.is_empty() {
+        options.push((None, end_weight));
+    }
+    
+    return options;
+}
+
+// The options after a node with more than one option, split by who sends first. The
+// side that sends the node's burst (the client at the root) decides.
+struct Branch {
+    own: Vec<(usize, f64)>,   // bursts the deciding side sends next, with their weights
+    other: Vec<(usize, f64)>, // bursts the other side sends next, with their weights
+    end_weight: f64,          // weight of the end of the reference
+}
+
+// The states a node needs on one side, beyond its burst state (see generate_states).
+#[derive(Clone, Copy, Default)]
+struct Extra {
+    hold: Option<usize>,
+    drain: Option<usize>,
+    decode: Option<usize>,
+    end_hold: Option<usize>,
+    marker: Option<usize>,
+    marker_hold: Option<usize>,
+}
+
+// Generate the states of the client (relay = false) or relay (relay = true) machine.
+fn generate_states(nodes: &[Node], relay: bool, send_timeout: &Dist, sync_timeout: f64, tail: &Tail) -> Vec<State> {
+    // Layout: START, BLOCK (the root), one burst state per node, then the states each
+    // node needs on this side:
+    // HOLD        --> after a burst that the other side decodes by silence
+    // DRAIN       --> after the smallest size of a burst that varies in size
+    // DECODE      --> at a divergence point, counts the bursts the other side may send
+    //                 next, and times its silence if the other side decides
+    // END_HOLD    --> for the deciding side, before the end its silence stood for
+    // END, HOLD   --> the END marker burst after the deciding side's silence
+    // and finally the TAIL state for a regulator tail, and UNBLOCK.
+    let (regulator_tail, tail_rate, tail_duration) = match *tail {
+        Tail::Regulator(client_rate, relay_rate, duration) => (true, if relay { relay_rate } else { client_rate }, duration),
+        _ => (false, 0.0, 0.0),
+    };
+    
+    // The other side decodes a burst after sync_timeout without a cell. A HOLD lasts
+    // longer than that, but the next burst must start before the other side's silence
+    // timeout runs out again, at twice sync_timeout.
+    let hold_timeout = 1.25 * sync_timeout;
+    let max_pacing = 0.5 * sync_timeout;
+    
+    let branches: Vec<Option<Branch>> = (0..nodes.len()).map(|node_index| split_options(nodes, node_index)).collect();
+    
+    // Bursts decoded among other sizes or varying in size (so their pacing is capped),
+    // and bursts decoded by the silence after them (as they might be the start of a
+    // larger one, or go on past their smallest size)
+    let varied = |node_index: usize| -> bool {
+        match nodes[node_index].step {
+            Some(ref step) => step.max_cells > step.num_cells,
+            None => false,
+        }
+    };
+    let mut decoded: Vec<bool> = (0..nodes.len()).map(&varied).collect();
+    let mut confirmed: Vec<bool> = decoded.clone();
+    for branch in branches.iter().flatten() {
+        for &deciding in [false, true].iter() {
+            let options = decode_options(nodes, branch, deciding);
+            let num_options = options.len() + (!deciding && has_silence(branch)) as usize;
+            let largest = options.iter().map(|&(size, _)| size).max().unwrap_or(0);
+            for &(size, child) in options.iter() {
+                if let Some(child) = child {
+                    decoded[child] |= num_options > 1;
+                    confirmed[child] |= size < largest;
+                }
+            }
+        }
+    }
+    
+    let mut extras: Vec<Extra> = vec![Extra::default(); nodes.len()];
+    let mut num_states = nodes.len() + 1;
+    
+    for node_index in 0..nodes.len() {
+        let extra = &mut extras[node_index];
+        let deciding = sender(nodes, node_index) == relay;
+        if node_index > 0 && deciding && confirmed[node_index] && sends_after(nodes, node_index) {
+            extra.hold = Some(num_states);
+            num_states += 1;
+        }
+        if !deciding && varied(node_index) {
+            extra.drain = Some(num_states);
+            num_states += 1;
+        }
+        
+        let branch = match branches[node_index] {
+            Some(ref branch) => branch,
+            None => continue,
+        };
+        
+        if deciding {
+            if branch.other.is_empty() {
+                extra.end_hold = Some(num_states);
+                num_states += 1;
+            } else {
+                extra.decode = Some(num_states);
+                num_states += decode_len(&decode_options(nodes, branch, true), false);
+            }
+            continue;
+        }
+        
+        if !branch.own.is_empty() {
+            extra.decode = Some(num_states);
+            num_states += decode_len(&decode_options(nodes, branch, false), has_silence(branch));
+        }
+        if !branch.other.is_empty() && branch.end_weight > 0.0 {
+            extra.marker = Some(num_states);
+            num_states += 1;
+            
+            let options = decode_options(nodes, branch, true);
+            if end_marker_size(nodes, branch) < options[options.len() - 1].0 {
+                extra.marker_hold = Some(num_states);
+                num_states += 1;
+            }
+        }
+    }
+    if regulator_tail {
+        num_states += 1;
+    }
+    num_states += 1;
+    let unblock_index = num_states - 1;
+    let end_index = if regulator_tail { unblock_index - 1 } else { unblock_index }; // TAIL or UNBLOCK
+    
+    // Target state of an option: the burst state of the child node, or the end of
+    // the reference
+    let target = |child: Option<usize>| -> usize {
+        match child {
+            Some(child) => child + 1,
+            None => end_index,
+        }
+    };
+    
+    // What the side that does not decide picks after the deciding side's silence
+    let choice = |branch: &Branch, node_index: usize| -> HashMap<usize, f64> {
+        let total_weight = branch.other.iter().map(|(_, weight)| weight).sum::<f64>() + branch.end_weight;
+        let mut next: HashMap<usize, f64> = HashMap::new();
+        for &(child, weight) in branch.other.iter() {
+            next.insert(child + 1, weight / total_weight);
+        }
+        if branch.end_weight > 0.0 {
+            next.insert(extras[node_index].marker.unwrap_or(end_index), branch.end_weight / total_weight);
+        }
+        return next;
+    };
+    
+    // What this side does once the burst of a node is over
+    let after = |node_index: usize| -> HashMap<usize, f64> {
+        let branch = match branches[node_index] {
+            Some(ref branch) => branch,
+            None => return transition_to(target(branch_options(nodes, node_index)[0].0)),
+        };
+        let extra = &extras[node_index];
+        
+        // The other side decides: wait for its burst or silence, or pick right away
+        if sender(nodes, node_index) != relay {
+            return match extra.decode {
+                Some(decode_index) => transition_to(decode_index),
+                None => choice(branch, node_index),
+            };
+        }
+        
+        // This side decides: one of its bursts, or silence
+        let own_weight: f64 = branch.own.iter().map(|(_, weight)| weight).sum();
+        let silence_weight = branch.other.iter().map(|(_, weight)| weight).sum::<f64>() + branch.end_weight;
+        let mut next: HashMap<usize, f64> = HashMap::new();
+        for &(child, weight) in branch.own.iter() {
+            next.insert(child + 1, weight / (own_weight + silence_weight));
+        }
+        if silence_weight > 0.0 {
+            next.insert(extra.decode.or(extra.end_hold).unwrap(), silence_weight / (own_weight + silence_weight));
+        }
+        return next;
+    };
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    states.push(generate_start_state(1, num_states));
+    
+    // BLOCK and burst states
+    for node_index in 0..nodes.len() {
+        // LimitReached (BlockingBegin for BLOCK) --> HOLD, DRAIN, or what follows the burst
+        let next = match extras[node_index].hold.or(extras[node_index].drain) {
+            Some(next_index) => transition_to(next_index),
+            None => after(node_index),
+        };
+        
+        let curr_index = node_index + 1;
+        match nodes[node_index].step {
+            None => states.push(generate_block_state(next, 0.0, num_states)),
+            Some(ref step) => {
+                if step.relay_sending == relay {
+                    let mut timeout = step.timeout.clone();
+                    if decoded[node_index] {
+                        timeout = bounded_dist(&timeout, max_pacing);
+                    }
+                    let mut state = generate_send_state(step.num_cells as f64, &timeout, curr_index, next, num_states);
+                    if step.max_cells > step.num_cells {
+                        // The limit is sampled on each execution, and truncated to whole cells
+                        let mut limit = uniform_dist(step.num_cells as f64, (step.max_cells + 1) as f64);
+                        limit.max = step.max_cells as f64;
+                        state.limit = limit;
+                    }
+                    states.push(state);
+                } else {
+                    states.push(generate_recv_state(step.num_cells as f64, curr_index, next, num_states));
+                }
+            }
+        }
+    }
+    
+    // HOLD, DRAIN, DECODE, END_HOLD and END states
+    for node_index in 0..nodes.len() {
+        let extra = extras[node_index];
+        if extra.hold.is_some() {
+            states.push(generate_block_state(after(node_index), hold_timeout, num_states));
+        }
+        if let Some(drain_index) = extra.drain {
+            states.push(generate_quiet_state(transition_to(drain_index), after(node_index), sync_timeout, num_states));
+        }
+        
+        let branch = match branches[node_index] {
+            Some(ref branch) => branch,
+            None => continue,
+        };
+        let deciding = sender(nodes, node_index) == relay;
+        
+        if let Some(decode_index) = extra.decode {
+            let options: Vec<(usize, HashMap<usize, f64>)> = decode_options(nodes, branch, deciding).iter()
+                .map(|&(size, child)| (size, match child {
+                    Some(child) => after(child),
+                    None => transition_to(end_index),
+                }))
+                .collect();
+            let mut silence: Option<HashMap<usize, f64>> = None;
+            if !deciding && has_silence(branch) {
+                silence = Some(choice(branch, node_index));
+            }
+            states.extend(generate_decode_states(decode_index, &options, silence, sync_timeout, num_states));
+        }
+        if extra.end_hold.is_some() {
+            states.push(generate_block_state(transition_to(end_index), hold_timeout, num_states));
+        }
+        if let Some(marker_index) = extra.marker {
+            let next = transition_to(extra.marker_hold.unwrap_or(end_index));
+            states.push(generate_send_state(end_marker_size(nodes, branch) as f64, send_timeout, marker_index, next, num_states));
+            if extra.marker_hold.is_some() {
+                states.push(generate_block_state(transition_to(end_index), hold_timeout, num_states));
+            }
+        }
+    }
+    
+    // TAIL state
+    if regulator_tail {
+        let tail_cells = (tail_rate * tail_duration).round().max(1.0);
+        let next = transition_to(unblock_index);
+        states.push(generate_send_state(tail_cells, &constant_dist(1000000.0 / tail_rate), unblock_index - 1, next, num_states));
+    }
+    
+    // UNBLOCK state, which lets the rest of the page through
+    states.push(generate_unblock_state(num_states));
+    
+    return states;
+}
+
+// The side that sends the burst of a node, and so decides after it: relay (true) or
+// client (false). The client decides at the root.
+fn sender(nodes: &[Node], node_index: usize) -> bool {
+    return match nodes[node_index].step {
+        Some(ref step) => step.relay_sending,
+        None => false,
+    };
+}
+
+// Number of cells in the burst of a node, which the other side decodes it by.
+fn burst_size(nodes: &[Node], node_index: usize) -> usize {
+    return nodes[node_index].step.as_ref().unwrap().num_cells;
+}
+
+// Split the options after a node, if there is more than one.
+fn split_options(nodes: &[Node], node_index: usize) -> Option<Branch> {
+    let options = branch_options(nodes, node_index);
+    if options.len() < 2 {
+        return None;
+    }
+    
+    let deciding_side = sender(nodes, node_index);
+    let mut branch = Branch {
+        own: Vec::new(),
+        other: Vec::new(),
+        end_weight: 0.0,
+    };
+    for &(child, weight) in options.iter() {
+        match child {
+            Some(child) if sender(nodes, child) == deciding_side => branch.own.push((child, weight)),
+            Some(child) => branch.other.push((child, weight)),
+            None => branch.end_weight += weight,
+        }
+    }
+    
+    return Some(branch);
+}
+
+// Whether the deciding side may stay silent, leaving the choice to the other side.
+fn has_silence(branch: &Branch) -> bool {
+    return !branch.other.is_empty() || branch.end_weight > 0.0;
+}
+
+// Size of the END marker burst: the smallest size that none of the other side's
+// bursts has.
+fn end_marker_size(nodes: &[Node], branch: &Branch) -> usize {
+    let mut size = 1;
+    while branch.other.iter().any(|&(child, _)| burst_size(nodes, child) == size) {
+        size += 1;
+    }
+    
+    return size;
+}
+
+// The bursts that one side decodes at a divergence point, as (size, node), sorted by
+// size: the deciding side's bursts for the other side, and for the deciding side
+// (deciding = true), the other side's bursts and the END marker (None) after silence.
+fn decode_options(nodes: &[Node], branch: &Branch, deciding: bool) -> Vec<(usize, Option<usize>)> {
+    let mut options: Vec<(usize, Option<usize>)> = Vec::new();
+    if deciding {
+        for &(child, _) in branch.other.iter() {
+            options.push((burst_size(nodes, child), Some(child)));
+        }
+        if !branch.other.is_empty() && branch.end_weight > 0.0 {
+            options.push((end_marker_size(nodes, branch), None));
+        }
+    } else {
+        for &(child, _) in branch.own.iter() {
+            options.push((burst_size(nodes, child), Some(child)));
+        }
+    }
+    
+    options.sort_by_key(|&(size, _)| size);
+    return options;
+}
+
+// Whether the side that sends the burst of a node goes on without waiting for the
+// other side after it: with a burst of its own, a choice of its own, or the end.
+fn sends_after(nodes: &[Node], node_index: usize) -> bool {
+    if let Some(branch) = split_options(nodes, node_index) {
+        return !branch.own.is_empty();
+    }
+    
+    return match branch_options(nodes, node_index)[0].0 {
+        Some(child) => sender(nodes, child) == sender(nodes, node_index),
+        None => true,
+    };
+}
+
+// Cap the samples of a distribution at max.
+fn bounded_dist(dist: &Dist, max: f64) -> Dist {
+    let mut dist = dist.clone();
+    if dist.max == 0.0 || dist.max > max {
+        dist.max = max;
+    }
+    
+    return dist;
+}
+
+// Largest sample of a distribution, if it is bounded: its max, or the end of a uniform
+// range.
+pub fn dist_max(dist: &Dist) -> Option<f64> {
+    let mut bound = match dist.dist {
+        DistType::Uniform => Some(dist.start + dist.param2),
+        _ => None,
+    };
+    if dist.max > 0.0 {
+        bound = Some(bound.map_or(dist.max, |bound| bound.min(dist.max)));
+    }
+    
+    return bound;
+}
+
+// Convert the bursts of a reference into burst state pairs. Untimed bursts use
+// send_timeout between cells. Timed bursts are split in two: the first cell is
+// sent after the gap since the previous burst ended, and the remaining cells are
+// spread evenly over the duration of the burst. Noise applies to the size of the
+// burst, or of the remaining cells for timed bursts.
+fn generate_steps(bursts: &[Burst], timed: bool, send_timeout: &Dist) -> Vec<Step> {
+    let mut steps: Vec<Step> = Vec::with_capacity(bursts.len() * 2);
+    let mut prev_end = 0.0;
+    
+    for burst in bursts.iter() {
+        if !timed {
+            steps.push(Step {
+                relay_sending: burst.relay_sending,
+                num_cells: burst.num_cells,
+                max_cells: burst.num_cells,
+                timeout: send_timeout.clone(),
+                noisy: true,
+            });
+            continue;
+        }
+        
+        let gap = (burst.start - prev_end).max(0.0);
+        steps.push(Step {
+            relay_sending: burst.relay_sending,
+            num_cells: 1,
+            max_cells: 1,
+            timeout: constant_dist(gap),
+            noisy: false,
+        });
+        
+        if burst.num_cells > 1 {
+            let pacing = burst.duration / ((burst.num_cells - 1) as f64);
+            steps.push(Step {
+                relay_sending: burst.relay_sending,
+                num_cells: burst.num_cells - 1,
+                max_cells: burst.num_cells - 1,
+                timeout: constant_dist(pacing),
+                noisy: true,
+            });
+        }
+        
+        prev_end = burst.start + burst.duration;
+    }
+    
+    return steps;
+}
+
+// Number of states of a DECODE chain for the given options (see generate_decode_states).
+fn decode_len(options: &[(usize, Option<usize>)], silence: bool) -> usize {
+    let mut len = options.len() - 1 + silence as usize;
+    let mut counted = 0;
+    for (k, &(size, _)) in options.iter().enumerate() {
+        let mut remaining = size - counted;
+        if silence || k > 0 {
+            remaining -= 1;
+        }
+        if remaining > 0 {
+            len += 1;
+        }
+        counted = size;
+    }
+    
+    return len;
+}
+
+// Generate a DECODE chain, which counts the cells of a burst, tells options apart by its
+// size, and moves on to the next states of the option. Options are (size, next), with
+// distinct sizes in increasing order. With silence set, the chain first waits for the
+// timeout without a cell, and moves on to silence if none arrives. Each option but the
+// largest is only decoded once no cell arrives for the timeout after its last cell.
+// Layout: QUIET_0 (with silence), then for each option k, RECV_k (if cells are left to
+// count once the first cell past the previous option arrived) and QUIET_k (but the last).
+fn generate_decode_states(first_index: usize, options: &[(usize, HashMap<usize, f64>)], silence: Option<HashMap<usize, f64>>, timeout: f64, num_states: usize) -> Vec<State> {
+    let mut remaining: Vec<usize> = Vec::with_capacity(options.len());
+    let mut recv_index: Vec<Option<usize>> = Vec::with_capacity(options.len());
+    let mut quiet_index: Vec<Option<usize>> = Vec::with_capacity(options.len());
+    
+    let mut index = first_index + silence.is_some() as usize;
+    let mut counted = 0;
+    for (k, (size, _)) in options.iter().enumerate() {
+        // A cell arriving in a QUIET state is the first cell past the previous option
+        let mut cells = size - counted;
+        if silence.is_some() || k > 0 {
+            cells -= 1;
+        }
+        remaining.push(cells);
+        counted = *size;
+        
+        recv_index.push(None);
+        if cells > 0 {
+            recv_index[k] = Some(index);
+            index += 1;
+        }
+        quiet_index.push(None);
+        if k + 1 < options.len() {
+            quiet_index[k] = Some(index);
+            index += 1;
+        }
+    }
+    
+    // Once the cells of option k are counted --> QUIET_k, or its next for the last option
+    let counted_next = |k: usize| -> HashMap<usize, f64> {
+        match quiet_index[k] {
+            Some(quiet_index) => transition_to(quiet_index),
+            None => options[k].1.clone(),
+        }
+    };
+    
+    // On the first cell past the previous option --> RECV_k, or on as for counted cells
+    let recv_next = |k: usize| -> HashMap<usize, f64> {
+        match recv_index[k] {
+            Some(recv_index) => transition_to(recv_index),
+            None => counted_next(k),
+        }
+    };
+    
+    let mut states: Vec<State> = Vec::with_capacity(index - first_index);
+    if let Some(silence) = silence {
+        states.push(generate_quiet_state(recv_next(0), silence, timeout, num_states));
+    }
+    for k in 0..options.len() {
+        if let Some(curr_index) = recv_index[k] {
+            states.push(generate_recv_state(remaining[k] as f64, curr_index, counted_next(k), num_states));
+        }
+        if quiet_index[k].is_some() {
+            states.push(generate_quiet_state(recv_next(k + 1), options[k].1.clone(), timeout, num_states));
+        }
+    }
+    
+    return states;
+}
+
+// Generate a QUIET state, which moves on to recv_next when a cell arrives, or to
+// quiet_next once none has arrived for the timeout, by re-blocking and moving on at
+// BlockingBegin. Cells only go out in place of padding while the machines block, so each
+// cell received is a cell of the burst being decoded, whether or not it is padding.
+fn generate_quiet_state(recv_next: HashMap<usize, f64>, quiet_next: HashMap<usize, f64>, timeout: f64, num_states: usize) -> State {
+    // NonPaddingRecv, PaddingRecv --> recv_next, BlockingBegin --> quiet_next
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::NonPaddingRecv, recv_next.clone());
+    transitions.insert(Event::PaddingRecv, recv_next);
+    transitions.insert(Event::BlockingBegin, quiet_next);
+    
+    // QUIET state
+    let mut state = State::new(transitions, num_states);
+    state.action_is_block = true;
+    state.bypass = true;
+    state.replace = true;
+    
+    state.timeout = constant_dist(timeout);
+    
+    state.action = constant_dist(INFINITY);
+    
+    return state;
+}
+
+// Errors from reading a reference trace
+pub enum ReferenceError {
+    Io(String, io::Error),        // file, error
+    Format(String, usize, String), // file, line number, description
+}
+
+impl fmt::Display for ReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReferenceError::Io(file, err) => write!(f, "{}: {}", file, err),
+            ReferenceError::Format(file, line, description) => write!(f, "{}:{}: {}", file, line, description),
+        }
+    }
+}
+
+impl From<TraceError> for ReferenceError {
+    fn from(err: TraceError) -> Self {
+        match err {
+            TraceError::Io(file, err) => ReferenceError::Io(file, err),
+            TraceError::Format(file, line, description) => ReferenceError::Format(file, line, description),
+        }
+    }
+}
+
+// Print a reference error and exit.
+pub fn exit_on_error<T>(result: Result<T, ReferenceError>) -> T {
+    return result.unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    });
+}
+
+// Read a reference trace. Two formats are accepted:
+//  - untimed: one burst size per line, with 0 toggling the direction
+//  - timed:   "<direction> <num cells> <start> <duration>" per line, where direction
+//             is 1 (client to relay) or -1 (relay to client) and times are in seconds
+// The format is set by the first burst line. Blank lines and anything after a # are
+// ignored. Only the first cutoff_length bursts are kept, but the whole file is checked,
+// with a warning if bursts were dropped.
+// Returns the bursts and whether the reference is timed.
+pub fn read_lines(filename: &str, cutoff_length: usize) -> Result<(Vec<Burst>, bool), ReferenceError> {
+    let file = File::open(filename).map_err(|err| ReferenceError::Io(filename.to_string(), err))?;
+    let reader = BufReader::new(file);
+    
+    let format_error = |line_number: usize, description: String| ReferenceError::Format(filename.to_string(), line_number, description);
+    
+    let mut bursts: Vec<Burst> = Vec::new();
+    let mut timed: Option<bool> = None;
+    let mut relay_sending = false;
+    let mut dropped_bursts = 0;
+    let mut cutoff_line = 0;
+    
+    for (idx, line) in reader.lines().enumerate() {
+        let line_number = idx + 1;
+        let line = line.map_err(|err| ReferenceError::Io(filename.to_string(), err))?;
+        
+        let content = line.split('#').next().unwrap();
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        
+        let line_timed = *timed.get_or_insert(fields.len() > 1);
+        let burst = if line_timed {
+            if fields.len() != 4 {
+                return Err(format_error(line_number, format!("expected \"<direction> <num cells> <start> <duration>\", found {} fields", fields.len())));
+            }
+            
+            let direction: i32 = fields[0].parse().map_err(|_| format_error(line_number, format!("invalid direction \"{}\"", fields[0])))?;
+            let num_cells: usize = fields[1].parse().map_err(|_| format_error(line_number, format!("invalid number of cells \"{}\"", fields[1])))?;
+            let start: f64 = fields[2].parse().map_err(|_| format_error(line_number, format!("invalid start \"{}\"", fields[2])))?;
+            let duration: f64 = fields[3].parse().map_err(|_| format_error(line_number, format!("invalid duration \"{}\"", fields[3])))?;
+            
+            if direction != 1 && direction != -1 {
+                return Err(format_error(line_number, format!("direction must be 1 or -1, found {}", direction)));
+            }
+            if start.is_nan() || duration.is_nan() || start < 0.0 || duration < 0.0 {
+                return Err(format_error(line_number, String::from("start and duration must not be negative")));
+            }
+            
+            Burst {
+                relay_sending: direction == -1,
+                num_cells: num_cells,
+                start: start * 1000000.0,
+                duration: duration * 1000000.0,
+            }
+        } else {
+            if fields.len() != 1 {
+                return Err(format_error(line_number, format!("expected a single burst size (untimed format), found {} fields", fields.len())));
+            }
+            
+            let num_cells: usize = fields[0].parse().map_err(|_| format_error(line_number, format!("invalid burst size \"{}\"", fields[0])))?;
+            let burst_relay_sending = relay_sending;
+            relay_sending = !relay_sending;
+            
+            Burst {
+                relay_sending: burst_relay_sending,
+                num_cells: num_cells,
+                start: 0.0,
+                duration: 0.0,
+            }
+        };
+        
+        if burst.num_cells == 0 {
+            continue;
+        }
+        
+        if bursts.len() < cutoff_length {
+            bursts.push(burst);
+        } else {
+            if dropped_bursts == 0 {
+                cutoff_line = line_number;
+            }
+            dropped_bursts += 1;
+        }
+    }
+    
+    if dropped_bursts > 0 {
+        eprintln!("Warning: {}:{}: truncated to {} bursts, dropping {} bursts", filename, cutoff_line, cutoff_length, dropped_bursts);
+    }
+    
+    return Ok((bursts, timed.unwrap_or(false)));
+}
+
+// Read a cell-level trace (see trace::read_trace) and return its bursts, and whether
+// they are timed. A burst is a run of cells in the same direction, where consecutive
+// cells are at most burst_gap microseconds apart (timed traces only).
+pub fn read_cell_trace(filename: &str, format: TraceFormat, burst_gap: f64) -> Result<(Vec<Burst>, bool), ReferenceError> {
+    let cells = try_read_trace(filename, format)?;
+    
+    let mut bursts: Vec<Burst> = Vec::new();
+    let mut last_time = 0.0;
+    
+    for cell in cells.iter() {
+        if let Some(burst) = bursts.last_mut() {
+            if burst.relay_sending == cell.relay_sending && cell.time - last_time <= burst_gap {
+                burst.num_cells += 1;
+                burst.duration = cell.time - burst.start;
+                last_time = cell.time;
+                continue;
+            }
+        }
+        
+        bursts.push(Burst {
+            relay_sending: cell.relay_sending,
+            num_cells: 1,
+            start: cell.time,
+            duration: 0.0,
+        });
+        last_time = cell.time;
+    }
+    
+    return Ok((bursts, format == TraceFormat::Wang));
+}
+
+// Write bursts as a reference trace, in the timed or untimed format (see read_lines).
+pub fn write_reference(filename: &str, bursts: &[Burst], timed: bool) -> Result<(), ReferenceError> {
+    let io_error = |err: io::Error| ReferenceError::Io(filename.to_string(), err);
+    
+    let file = File::create(filename).map_err(io_error)?;
+    let mut writer = BufWriter::new(file);
+    
+    let mut relay_sending = false;
+    for burst in bursts.iter() {
+        if timed {
+            let direction = if burst.relay_sending { -1 } else { 1 };
+            writeln!(writer, "{} {} {:.6} {:.6}", direction, burst.num_cells, burst.start / 1000000.0, burst.duration / 1000000.0)
+                .map_err(io_error)?;
+            continue;
+        }
+        
+        if burst.relay_sending != relay_sending {
+            writeln!(writer, "0").map_err(io_error)?;
+        }
+        writeln!(writer, "{}", burst.num_cells).map_err(io_error)?;
+        relay_sending = !burst.relay_sending;
+    }
+    
+    return writer.flush().map_err(io_error);
+}
@@ -0,0 +1,167 @@
+// Cell-level traces -- reading and writing the trace formats used by the tools
+
+use std::fs;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+pub const TOR_CELL_SIZE: f64 = 512.0;
+
+// A cell in a trace, as seen by the client
+#[derive(Clone, Copy)]
+pub struct Cell {
+    pub time: f64,           // microseconds from the first cell of the trace
+    pub relay_sending: bool, // direction: relay to client (true) or client to relay (false)
+}
+
+// Format of cell-level traces
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Wang,   // one "<timestamp> <direction>" line per cell, as in the Wang and DF datasets
+    Signed, // signed sizes only, separated by whitespace or commas
+}
+
+pub fn parse_trace_format(name: &str) -> TraceFormat {
+    match name {
+        "wang" => TraceFormat::Wang,
+        "signed" => TraceFormat::Signed,
+        _ => panic!("Unknown trace format: {}", name),
+    }
+}
+
+// Expand paths, replacing each directory by the files in it (sorted by name).
+pub fn list_files(paths: &[String]) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    
+    for path in paths.iter() {
+        if !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        
+        let mut entries: Vec<String> = fs::read_dir(path)
+            .expect("Couldn't read directory")
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|entry| entry.is_file())
+            .map(|entry| entry.to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        files.append(&mut entries);
+    }
+    
+    return files;
+}
+
+// Errors from reading or writing a trace
+pub enum TraceError {
+    Io(String, io::Error),         // file, error
+    Format(String, usize, String), // file, line number, description
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(file, err) => write!(f, "{}: {}", file, err),
+            TraceError::Format(file, line, description) => write!(f, "{}:{}: {}", file, line, description),
+        }
+    }
+}
+
+// Read a cell-level trace, panicking on errors (see try_read_trace).
+pub fn read_trace(filename: &str, format: TraceFormat) -> Vec<Cell> {
+    return try_read_trace(filename, format).unwrap_or_else(|err| panic!("{}", err));
+}
+
+// Read a cell-level trace.
+//  - wang:   the timestamp is in seconds, and the sign of the direction gives the
+//            direction (positive is client to relay), so one cell per line
+//  - signed: each value is a cell if it is 1 or -1, and otherwise a size in bytes,
+//            counted as that many cells; positive is client to relay; no timing
+pub fn try_read_trace(filename: &str, format: TraceFormat) -> Result<Vec<Cell>, TraceError> {
+    let file = File::open(filename).map_err(|err| TraceError::Io(filename.to_string(), err))?;
+    let reader = BufReader::new(file);
+    
+    let format_error = |line_number: usize, description: String| TraceError::Format(filename.to_string(), line_number, description);
+    
+    let mut cells: Vec<Cell> = Vec::new();
+    
+    for (idx, line) in reader.lines().enumerate() {
+        let line_number = idx + 1;
+        let line = line.map_err(|err| TraceError::Io(filename.to_string(), err))?;
+        
+        if format == TraceFormat::Wang {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            
+            if fields.len() < 2 {
+                return Err(format_error(line_number, String::from("expected \"<timestamp> <direction>\"")));
+            }
+            let time: f64 = fields[0].parse().map_err(|_| format_error(line_number, format!("invalid timestamp \"{}\"", fields[0])))?;
+            let direction: f64 = fields[1].parse().map_err(|_| format_error(line_number, format!("invalid direction \"{}\"", fields[1])))?;
+            cells.push(Cell {
+                time: time * 1000000.0,
+                relay_sending: direction < 0.0,
+            });
+            continue;
+        }
+        
+        for field in line.split(|c: char| c.is_whitespace() || c == ',').filter(|field| !field.is_empty()) {
+            let size: f64 = field.parse().map_err(|_| format_error(line_number, format!("invalid value \"{}\"", field)))?;
+            let mut num_cells = 1;
+            if size.abs() > 1.0 {
+                num_cells = (size.abs() / TOR_CELL_SIZE).ceil() as usize;
+            }
+            
+            for _ in 0..num_cells {
+                cells.push(Cell {
+                    time: 0.0,
+                    relay_sending: size < 0.0,
+                });
+            }
+        }
+    }
+    
+    // Times are relative to the first cell
+    if let Some(first_time) = cells.first().map(|cell| cell.time) {
+        for cell in cells.iter_mut() {
+            cell.time -= first_time;
+        }
+    }
+    
+    return Ok(cells);
+}
+
+// Write a trace in the Wang format.
+pub fn write_trace(filename: &str, cells: &[Cell]) -> Result<(), TraceError> {
+    let io_error = |err: io::Error| TraceError::Io(filename.to_string(), err);
+    let file = File::create(filename).map_err(io_error)?;
+    let mut writer = BufWriter::new(file);
+    
+    for cell in cells.iter() {
+        let direction = if cell.relay_sending { -1 } else { 1 };
+        writeln!(writer, "{:.6}\t{}", cell.time / 1000000.0, direction).map_err(io_error)?;
+    }
+    writer.flush().map_err(io_error)?;
+    
+    return Ok(());
+}
+
+// Site label of a trace in a labelled dataset, from its file name: "<site>-<instance>" for
+// monitored sites, and "<instance>" for unmonitored sites (None), as in the Wang dataset.
+// Any extension is ignored.
+pub fn site_label(filename: &str) -> Option<usize> {
+    let stem = Path::new(filename).file_stem().expect("Trace file has no name").to_string_lossy();
+    
+    match stem.split_once('-') {
+        Some((site, _)) => Some(site.parse().unwrap_or_else(|_| panic!("Trace file name is not <site>-<instance>: {}", filename))),
+        None => {
+            stem.parse::<usize>().unwrap_or_else(|_| panic!("Trace file name is not <site>-<instance> or <instance>: {}", filename));
+            None
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// WTF-PAD -- the BURST and GAP padding machine of Maybenot WTF-PAD, which Maybenot
+// Interspace also generates, from sampled distributions
+
+use std::collections::HashMap;
+
+use maybenot::{
+machine::Machine,
+event::Event,
+state::State,
+dist::Dist
+};
+
+use crate::trace::TOR_CELL_SIZE;
+use crate::machines::{constant_dist, transition_to, generate_idle_state};
+
+// Generate a WTF-PAD machine for one side. After a non-padding cell, BURST sends padding
+// if no other non-padding cell is sent within a timeout from the burst distribution, i.e.,
+// to fill a gap between bursts. GAP then sends a fake burst, with timeouts from the gap
+// distribution and a length from the length distribution, and goes back to BURST (or to
+// START, as after the last burst of a trace). Non-padding cells return to BURST.
+pub fn generate_machine(burst: &Dist, gap: &Dist, length: &Dist, continue_prob: f64) -> String {
+    // Set up state vector
+    let num_states = 3;
+    
+    let mut states: Vec<State> = Vec::with_capacity(num_states);
+    
+    // START (waiting for a non-padding cell to be sent), BURST and GAP states
+    states.push(generate_idle_state(transition_to(1), num_states));
+    states.push(generate_burst_state(burst, num_states));
+    states.push(generate_gap_state(gap, length, continue_prob, num_states));
+    
+    // Machine construction
+    let machine = Machine {
+        allowed_padding_bytes: u64::MAX,
+        max_padding_frac: 0.0,
+        allowed_blocked_microsec: 0,
+        max_blocking_frac: 0.0,
+        states: states,
+        include_small_packets: false,
+    };
+    
+    return machine.serialize();
+}
+
+// Generate the GAP state, which sends a fake burst.
+pub fn generate_gap_state(gap: &Dist, length: &Dist, continue_prob: f64, num_states: usize) -> State {
+    // LimitReached --> BURST (continue_prob), START (1 - continue_prob)
+    let mut limit_reached: HashMap<usize, f64> = HashMap::new();
+    limit_reached.insert(1, continue_prob);
+    limit_reached.insert(0, 1.0 - continue_prob);
+    
+    // PaddingSent --> GAP (100%), NonPaddingSent --> BURST (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::PaddingSent, transition_to(2));
+    transitions.insert(Event::NonPaddingSent, transition_to(1));
+    transitions.insert(Event::LimitReached, limit_reached);
+    
+    // GAP state
+    let mut state = generate_padding_state(transitions, gap, num_states);
+    state.limit = length.clone();
+    
+    return state;
+}
+
+// Generate the BURST state, which waits for a gap between bursts.
+pub fn generate_burst_state(burst: &Dist, num_states: usize) -> State {
+    // PaddingSent --> GAP (100%), NonPaddingSent --> BURST (100%)
+    let mut transitions: HashMap<Event, HashMap<usize, f64>> = HashMap::new();
+    transitions.insert(Event::PaddingSent, transition_to(2));
+    transitions.insert(Event::NonPaddingSent, transition_to(1));
+    
+    return generate_padding_state(transitions, burst, num_states);
+}
+
+// Generate a state that sends a padding cell after a timeout from the given distribution.
+pub fn generate_padding_state(transitions: HashMap<Event, HashMap<usize, f64>>, timeout: &Dist, num_states: usize) -> State {
+    let mut state = State::new(transitions, num_states);
+    state.timeout = timeout.clone();
+    
+    state.action = constant_dist(TOR_CELL_SIZE);
+    
+    return state;
+}
@@ -0,0 +1,137 @@
+// Surakav branches -- checks that the machines decode each other's choice of the next
+// burst over a delayed link, so that both follow the same reference
+
+use std::env;
+use std::fs;
+use std::process;
+use std::process::Command;
+use std::str::FromStr;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use maybenot::machine::Machine;
+
+use maybenot_defenses::trace::Cell;
+use maybenot_defenses::simulator::{simulate, SimConfig};
+
+// Run maybenot_surakav on untimed references (one burst size per line, alternating
+// directions) with the given options, and parse the client and relay machines it prints.
+fn generate_machines(name: &str, references: &[&str], options: &[&str]) -> (Machine, Machine) {
+    let dir = env::temp_dir().join(format!("maybenot-surakav-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    
+    let mut ref_paths: Vec<String> = Vec::new();
+    for (j, reference) in references.iter().enumerate() {
+        let ref_path = dir.join(format!("ref-{}.txt", j));
+        fs::write(&ref_path, reference).unwrap();
+        ref_paths.push(ref_path.to_string_lossy().into_owned());
+    }
+    
+    let output = Command::new(env!("CARGO_BIN_EXE_maybenot_surakav")).args(&ref_paths).args(options).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "maybenot_surakav failed: {}", String::from_utf8_lossy(&output.stderr));
+    
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let machine = |label: &str| -> Machine {
+        let line = stdout.lines().find(|line| line.starts_with(label)).unwrap();
+        let serialized = line[label.len()..].split_whitespace().next().unwrap();
+        return Machine::from_str(serialized).unwrap();
+    };
+    
+    return (machine("Client machine: "), machine("Relay machine: "));
+}
+
+#[test]
+fn branch_decoding_survives_delay() {
+    // The references share the first client burst (5 cells), then diverge: the relay
+    // picks 10 or 20 cells, and the client answers with 3 or 7. If the client decoded
+    // the wrong size, its cells would not match the relay's choice.
+    let (client_machine, relay_machine) = generate_machines("branches", &["5\n10\n3\n", "5\n20\n7\n"], &[]);
+    let trace = vec![Cell {
+        time: 0.0,
+        relay_sending: false,
+    }];
+    
+    let mut followed: [bool; 2] = [false, false];
+    for &delay in [10000.0, 50000.0, 200000.0].iter() {
+        // One-way delays below, around and well above the sync timeout (20 ms)
+        let config = SimConfig {
+            delay: delay,
+            jitter: 0.0,
+            linger: 2000000.0,
+        };
+        
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let simulation = simulate(&[client_machine.clone()], &[relay_machine.clone()], &trace, config, &mut rng);
+            let relay_cells = simulation.cells.iter().filter(|cell| cell.relay_sending).count();
+            let client_cells = simulation.cells.len() - relay_cells;
+            
+            // Client: the real cell, the shared burst, then 3 or 7 cells, with no cells
+            // spent on agreeing on the branch
+            match (relay_cells, client_cells) {
+                (10, 9) => followed[0] = true,
+                (20, 13) => followed[1] = true,
+                _ => panic!("delay {} us, seed {}: relay sent {} cells and client {}, so the machines followed different references",
+                    delay, seed, relay_cells, client_cells),
+            }
+        }
+    }
+    
+    // The relay picks each reference with probability 1/2, so both come up in 30 runs
+    assert!(followed[0] && followed[1], "Only one reference was followed");
+}
+
+#[test]
+fn burst_sizes_vary_per_page_load() {
+    // With 50% jitter, the client sends 2 to 8 cells, the relay 20 to 60, and the client
+    // 1 to 5. The receiving side drains whatever the sending side drew, so each page
+    // load runs through all three bursts.
+    let (client_machine, relay_machine) = generate_machines("noise", &["5\n40\n3\n"], &["--jitter", "50", "--seed", "1"]);
+    let trace = vec![Cell {
+        time: 0.0,
+        relay_sending: false,
+    }];
+    let config = SimConfig {
+        delay: 50000.0,
+        jitter: 0.0,
+        linger: 2000000.0,
+    };
+    
+    let mut sizes: Vec<usize> = Vec::new();
+    for seed in 0..10 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let simulation = simulate(&[client_machine.clone()], &[relay_machine.clone()], &trace, config, &mut rng);
+        let relay_cells = simulation.cells.iter().filter(|cell| cell.relay_sending).count();
+        let client_cells = simulation.cells.len() - relay_cells;
+        
+        // Client: the real cell and two bursts
+        assert!((20..=60).contains(&relay_cells), "seed {}: relay sent {} cells", seed, relay_cells);
+        assert!((4..=14).contains(&client_cells), "seed {}: client sent {} cells", seed, client_cells);
+        
+        // The client's last cell comes after the relay's burst
+        let last_relay = simulation.cells.iter().rev().find(|cell| cell.relay_sending).unwrap();
+        let last_client = simulation.cells.iter().rev().find(|cell| !cell.relay_sending).unwrap();
+        assert!(last_client.time > last_relay.time, "seed {}: the client did not answer the relay's burst", seed);
+        sizes.push(relay_cells);
+    }
+    
+    sizes.sort();
+    sizes.dedup();
+    assert!(sizes.len() > 1, "The relay sent {} cells on every page load", sizes[0]);
+}
+
+#[test]
+fn slow_pacing_is_rejected() {
+    // Cells 15 ms apart could be taken for the end of a burst with the default sync
+    // timeout of 20 ms
+    let dir = env::temp_dir().join(format!("maybenot-surakav-pacing-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let ref_path = dir.join("ref.txt");
+    fs::write(&ref_path, "5\n10\n").unwrap();
+    
+    let output = Command::new(env!("CARGO_BIN_EXE_maybenot_surakav")).arg(&ref_path).args(&["--pacing", "ict:15000"]).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(!output.status.success(), "maybenot_surakav accepted pacing above half the sync timeout");
+}